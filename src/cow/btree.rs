@@ -1,9 +1,15 @@
 use sync::Arc;
 
 use std::mem;
+use std::cmp;
 
 use std::default::Default;
 use std::iter::range_step;
+use std::io::{IoResult, Reader, Writer};
+
+use snapshot::{SnapshotItem, invalid};
+use join::{Seekable, Limit, JoinSetIterator, AntiJoinSetIterator, UnionIterator, SymmetricDifferenceIterator};
+use join::{union, intersection, difference, symmetric_difference};
 
 static LEAF_SIZE: uint = 31;
 static INTERNAL_SIZE: uint = 42;
@@ -14,21 +20,24 @@ struct NodeLeaf<K, V> {
     values: [V, ..LEAF_SIZE] 
 }
 
-struct NodeInternal<K, V> {
+struct NodeInternal<K, V, S> {
     used:      uint,
     total_len: uint,
     keys:      [K, ..INTERNAL_SIZE-1],
-    children:  [Node<K, V>, ..INTERNAL_SIZE]
+    children:  [Node<K, V, S>, ..INTERNAL_SIZE],
+    // what each child summarizes to, kept next to the children so a
+    // parent never looks inside a leaf
+    summaries: [S, ..INTERNAL_SIZE]
 }
 
-enum Node<K, V> {
+enum Node<K, V, S> {
     Empty,
-    Internal(Arc<NodeInternal<K, V>>),
+    Internal(Arc<NodeInternal<K, V, S>>),
     Leaf(Arc<NodeLeaf<K, V>>),
 }
 
 pub struct BTreeMap<K, V> {
-    root: Node<K, V>
+    root: Node<K, V, ()>
 }
 
 enum InsertAction<K, V> {
@@ -42,9 +51,25 @@ fn default<T: Default>() -> T
     Default::default()
 }
 
-impl<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share> Clone for Node<K, V>
+// A monoid over the entries of a tree, e.g. the sum of the values.
+// Internal nodes keep one for each child, the way total_len counts the
+// entries below them. combine has to be associative with the default
+// value as its identity, it is always called in key order so it need
+// not be commutative. Plain maps use (), which keeps nothing.
+pub trait Summary<K, V>: Default+Clone+Send+Share {
+    fn summarize(key: &K, value: &V) -> Self;
+    fn combine(&mut self, other: &Self);
+}
+
+impl<K, V> Summary<K, V> for ()
+{
+    fn summarize(_: &K, _: &V) -> () { () }
+    fn combine(&mut self, _: &()) {}
+}
+
+impl<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share, S: Summary<K, V>> Clone for Node<K, V, S>
 {
-    fn clone(&self) -> Node<K, V>
+    fn clone(&self) -> Node<K, V, S>
     {
             match *self {
                 Empty => Empty,
@@ -54,7 +79,7 @@ impl<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share> Clone fo
     }
 }
 
-impl<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share> Node<K, V>
+impl<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share, S: Summary<K, V>> Node<K, V, S>
 {
     #[inline(always)]
     fn insert(&mut self, key: K, value: V) -> InsertAction<K, V>
@@ -82,7 +107,7 @@ impl<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share> Node<K,
         }        
     }
 
-    fn split(&mut self) -> (Node<K, V>, K)
+    fn split(&mut self) -> (Node<K, V, S>, K)
     {
         match *self {
             Leaf(ref mut leaf) => {
@@ -108,6 +133,147 @@ impl<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share> Node<K,
         }     
     }
 
+    // number of entries with a key that is below, those have to come
+    // before all the others
+    fn rank_by(&self, below: |&K| -> bool) -> uint
+    {
+        let mut rank = 0;
+        let mut node = self;
+        loop {
+            match *node {
+                Internal(ref n) => {
+                    let n = n.deref();
+                    let mut idx = 0;
+                    while idx < n.used-1 && below(&n.keys[idx]) {
+                        rank += n.children[idx].len();
+                        idx += 1;
+                    }
+                    node = &n.children[idx];
+                },
+                Leaf(ref l) => {
+                    let l = l.deref();
+                    let mut idx = 0;
+                    while idx < l.used && below(&l.keys[idx]) {
+                        idx += 1;
+                    }
+                    return rank + idx;
+                },
+                Empty => return rank
+            }
+        }
+    }
+
+    // number of entries with a key smaller than key
+    fn rank(&self, key: &K) -> uint
+    {
+        let mut rank = 0;
+        let mut node = self;
+        loop {
+            match *node {
+                Internal(ref n) => {
+                    let n = n.deref();
+                    let idx = n.search(key);
+                    for i in range(0, idx) {
+                        rank += n.children[i].len();
+                    }
+                    node = &n.children[idx];
+                },
+                Leaf(ref l) => {
+                    let (_, idx) = l.deref().search_key(key);
+                    return rank + idx;
+                },
+                Empty => return rank
+            }
+        }
+    }
+
+    #[inline(always)]
+    fn find<'a>(&'a self, key: &K) -> Option<&'a V>
+    {
+        let mut target = self;
+        let mut target_leaf: Option<&NodeLeaf<K, V>> = None;
+
+        while target_leaf.is_none() {
+            match *target {
+                Internal(ref node) => {
+                    target = &node.deref().children[node.deref().search(key)];
+                },
+                Leaf(ref leaf) => {
+                    target_leaf = Some(leaf.deref());
+                },
+                Empty => {
+                    return None;
+                },
+            };
+        }
+
+        target_leaf.unwrap().find(key)
+    }
+
+    // the entry with the smallest key, down the left edge
+    fn first<'a>(&'a self) -> Option<(&'a K, &'a V)>
+    {
+        let mut node = self;
+        loop {
+            match *node {
+                Internal(ref n) => node = &n.deref().children[0],
+                Leaf(ref l) => {
+                    let l = l.deref();
+                    return if l.used != 0 { Some((&l.keys[0], &l.values[0])) } else { None };
+                },
+                Empty => return None
+            }
+        }
+    }
+
+    // the entry with the largest key, down the right edge
+    fn last<'a>(&'a self) -> Option<(&'a K, &'a V)>
+    {
+        let mut node = self;
+        loop {
+            match *node {
+                Internal(ref n) => {
+                    let n = n.deref();
+                    node = &n.children[n.used-1];
+                },
+                Leaf(ref l) => {
+                    let l = l.deref();
+                    return if l.used != 0 { Some((&l.keys[l.used-1], &l.values[l.used-1])) } else { None };
+                },
+                Empty => return None
+            }
+        }
+    }
+
+    // the entry with idx entries before it
+    fn select<'a>(&'a self, idx: uint) -> Option<(&'a K, &'a V)>
+    {
+        let mut idx = idx;
+        let mut node = self;
+        loop {
+            match *node {
+                Internal(ref n) => {
+                    let n = n.deref();
+                    let mut i = 0;
+                    while i < n.used-1 && idx >= n.children[i].len() {
+                        idx -= n.children[i].len();
+                        i += 1;
+                    }
+                    node = &n.children[i];
+                },
+                Leaf(ref l) => {
+                    let l = l.deref();
+                    return if idx < l.used {
+                        Some((&l.keys[idx], &l.values[idx]))
+                    } else {
+                        None
+                    };
+                },
+                Empty => return None
+            }
+        }
+    }
+
     fn find_mut<'a>(&'a mut self, key: &K) -> Option<&'a mut V>
     {
         match *self {
@@ -118,7 +284,7 @@ impl<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share> Node<K,
     }
 
     // move the lowest key from other to self iff node is has extra keys
-    fn rotate_right(&mut self, src: &mut Node<K, V>) -> bool
+    fn rotate_right(&mut self, src: &mut Node<K, V, S>) -> bool
     {
         match (self, src) {
             (&Leaf(ref mut sink), &Leaf(ref mut src)) => {
@@ -134,7 +300,7 @@ impl<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share> Node<K,
     }
 
     // move highest key from src to self iff node is has extra keys
-    fn rotate_left(&mut self, src: &mut Node<K, V>) -> bool
+    fn rotate_left(&mut self, src: &mut Node<K, V, S>) -> bool
     {
         match (self, src) {
             (&Leaf(ref mut sink), &Leaf(ref mut src)) => {
@@ -150,7 +316,7 @@ impl<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share> Node<K,
     }
 
     // move highest key from src to self iff node is has extra keys
-    fn merge(&mut self, src: Node<K, V>)
+    fn merge(&mut self, src: Node<K, V, S>)
     {
         match (self, src) {
             (&Leaf(ref mut sink), Leaf(ref mut src)) => {
@@ -174,6 +340,195 @@ impl<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share> Node<K,
         }
     }
 
+    fn summary(&self) -> S
+    {
+        match *self {
+            Empty => default(),
+            Leaf(ref leaf) => leaf.deref().summary(),
+            Internal(ref node) => node.deref().summary()
+        }
+    }
+
+    // combine the summaries of the entries with lo <= key < hi into acc
+    // in key order, children that are covered entirely are not entered
+    fn fold_range(&self, lo: Option<&K>, hi: Option<&K>, acc: &mut S)
+    {
+        match *self {
+            Empty => (),
+            Leaf(ref leaf) => {
+                let leaf = leaf.deref();
+                let start = match lo {
+                    Some(lo) => { let (_, idx) = leaf.search_key(lo); idx },
+                    None => 0
+                };
+                let end = match hi {
+                    Some(hi) => { let (_, idx) = leaf.search_key(hi); idx },
+                    None => leaf.used
+                };
+                for i in range(start, end) {
+                    acc.combine(&Summary::summarize(&leaf.keys[i], &leaf.values[i]));
+                }
+            },
+            Internal(ref node) => {
+                let node = node.deref();
+                let first = match lo {
+                    Some(lo) => node.search(lo),
+                    None => 0
+                };
+                let last = match hi {
+                    Some(hi) => node.search(hi),
+                    None => node.used-1
+                };
+                if first == last {
+                    return node.children[first].fold_range(lo, hi, acc);
+                }
+
+                match lo {
+                    Some(_) => node.children[first].fold_range(lo, None, acc),
+                    None => acc.combine(&node.summaries[first])
+                }
+                for i in range(first+1, last) {
+                    acc.combine(&node.summaries[i]);
+                }
+                match hi {
+                    Some(_) => node.children[last].fold_range(None, hi, acc),
+                    None => acc.combine(&node.summaries[last])
+                }
+            }
+        }
+    }
+
+    // insert into the tree rooted at self, growing it when the root
+    // splits. Returns true if the key was already present.
+    fn insert_root(&mut self, key: K, value: V) -> bool
+    {
+        match self.insert(key, value) {
+            InsertDone(update) => update,
+            // if the left key is only updated
+            // on an insert, not an update so this
+            // can return false
+            InsertUpdateLeft(_) => false,
+            Split(key, value) => {
+                let (split_key, right) = match *self {
+                    Leaf(ref mut leaf) => {
+                        let (right, key) = leaf.make_unique().split();
+                        (key, Leaf(Arc::new(right)))
+                    },
+                    Internal(ref mut node) => {
+                        let (right, key) = node.make_unique().split();
+                        (key, Internal(Arc::new(right)))
+
+                    }
+                    _ => fail!("this is impossible")
+                };
+                let mut left = Empty;
+
+                mem::swap(self, &mut left);
+
+                *self = Internal(Arc::new(NodeInternal::new(split_key, left, right)));
+                self.insert_root(key, value)
+            }
+        }
+    }
+
+    // pop from the tree rooted at self, shrinking it when the root is
+    // left with a single child
+    fn pop_root(&mut self, key: &K) -> Option<V>
+    {
+        match self.pop(key) {
+            (_, found, false) => found,
+            (_, found, true) => {
+                self.lift();
+                found
+            }
+        }
+    }
+
+    // build a tree from entries with strictly increasing keys, the
+    // leaves are filled directly so nothing is searched or split. Other
+    // entries are inserted one by one, a later entry replacing an
+    // earlier one with the same key.
+    fn from_sorted_vec(entries: Vec<(K, V)>) -> Node<K, V, S>
+    {
+        let len = entries.len();
+        if len == 0 {
+            return Empty;
+        }
+
+        let sorted = range(1, len).all(|i| {
+            let &(ref prev, _) = entries.get(i-1);
+            let &(ref key, _) = entries.get(i);
+            prev.cmp(key) == Less
+        });
+        if !sorted {
+            let mut root = Empty;
+            for (key, value) in entries.move_iter() {
+                root.insert_root(key, value);
+            }
+            return root;
+        }
+
+        // spread the entries evenly so no node is left underfull
+        let leaves = (len + LEAF_SIZE - 1) / LEAF_SIZE;
+        let mut level = Vec::with_capacity(leaves);
+        let mut iter = entries.move_iter();
+        for i in range(0, leaves) {
+            let count = len / leaves + if i < len % leaves {1} else {0};
+            let mut leaf = NodeLeaf::new();
+            for j in range(0, count) {
+                let (key, value) = iter.next().unwrap();
+                leaf.keys[j] = key;
+                leaf.values[j] = value;
+            }
+            leaf.used = count;
+            level.push(Leaf(Arc::new(leaf)));
+        }
+
+        while level.len() > 1 {
+            let len = level.len();
+            let parents = (len + INTERNAL_SIZE - 1) / INTERNAL_SIZE;
+            let mut next = Vec::with_capacity(parents);
+            let mut iter = level.move_iter();
+            for i in range(0, parents) {
+                let count = len / parents + if i < len % parents {1} else {0};
+                let mut node = NodeInternal::new_empty();
+                for j in range(0, count) {
+                    if j != 0 {
+                        node.keys[j-1] = node.children[j-1].max_key();
+                    }
+                    node.children[j] = iter.next().unwrap();
+                    node.total_len += node.children[j].len();
+                    node.summaries[j] = node.children[j].summary();
+                }
+                node.used = count;
+                next.push(Internal(Arc::new(node)));
+            }
+            level = next;
+        }
+
+        level.pop().unwrap()
+    }
+
+    fn iter<'a>(&'a self) -> TreeIterator<'a, K, V, S>
+    {
+        let (leaf, stack) = match *self {
+            Leaf(ref leaf) => {
+                (Some(leaf.deref().iter()), ~[])
+            }
+            Internal(ref node) => {
+                (None, ~[node.deref().iter()])
+            },
+            Empty => (None, ~[])
+        };
+        TreeIterator {
+            root: self,
+            leaf: leaf,
+            stack: stack,
+            current: 0,
+            end: self.len()
+        }
+    }
+
     fn lift(&mut self)
     {
         let depleted = match *self {
@@ -196,20 +551,103 @@ impl<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share> Node<K,
     }
 }
 
-impl<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share> NodeInternal<K, V>
+// snapshots mirror the node layout, see snapshot.rs for the format
+impl<K: SnapshotItem+Default+Clone+TotalOrd+Send+Share, V: SnapshotItem+Default+Clone+Send+Share, S: Summary<K, V>> Node<K, V, S>
 {
-    fn new(key: K, left: Node<K, V>, right: Node<K, V>) -> NodeInternal<K, V>
+    fn write_snapshot<W: Writer>(&self, w: &mut W) -> IoResult<()>
+    {
+        match *self {
+            Empty => w.write_u8(0),
+            Leaf(ref leaf) => {
+                let leaf = leaf.deref();
+                try!(w.write_u8(1));
+                try!(w.write_le_u32(leaf.used as u32));
+                for i in range(0, leaf.used) {
+                    try!(leaf.keys[i].write_item(w));
+                }
+                for i in range(0, leaf.used) {
+                    try!(leaf.values[i].write_item(w));
+                }
+                Ok(())
+            },
+            Internal(ref node) => {
+                let node = node.deref();
+                try!(w.write_u8(2));
+                try!(w.write_le_u32(node.used as u32));
+                try!(w.write_le_u64(node.total_len as u64));
+                for i in range(0, node.used-1) {
+                    try!(node.keys[i].write_item(w));
+                }
+                for i in range(0, node.used) {
+                    try!(node.children[i].write_snapshot(w));
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn read_snapshot<R: Reader>(r: &mut R) -> IoResult<Node<K, V, S>>
+    {
+        match try!(r.read_u8()) {
+            0 => Ok(Empty),
+            1 => {
+                let mut leaf = NodeLeaf::new();
+                leaf.used = try!(r.read_le_u32()) as uint;
+                if leaf.used == 0 || leaf.used > LEAF_SIZE {
+                    return Err(invalid("bad leaf size"));
+                }
+                for i in range(0, leaf.used) {
+                    leaf.keys[i] = try!(SnapshotItem::read_item(r));
+                }
+                for i in range(0, leaf.used) {
+                    leaf.values[i] = try!(SnapshotItem::read_item(r));
+                }
+                Ok(Leaf(Arc::new(leaf)))
+            },
+            2 => {
+                let mut node = NodeInternal::new_empty();
+                node.used = try!(r.read_le_u32()) as uint;
+                if node.used < 2 || node.used > INTERNAL_SIZE {
+                    return Err(invalid("bad internal node size"));
+                }
+                let total_len: uint = try!(SnapshotItem::read_item(r));
+                for i in range(0, node.used-1) {
+                    node.keys[i] = try!(SnapshotItem::read_item(r));
+                }
+                for i in range(0, node.used) {
+                    node.children[i] = match try!(Node::read_snapshot(r)) {
+                        Empty => return Err(invalid("empty child node")),
+                        child => child
+                    };
+                    node.total_len += node.children[i].len();
+                    node.summaries[i] = node.children[i].summary();
+                }
+                if node.total_len != total_len {
+                    return Err(invalid("bad internal node length"));
+                }
+                Ok(Internal(Arc::new(node)))
+            },
+            _ => Err(invalid("bad node tag"))
+        }
+    }
+}
+
+impl<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share, S: Summary<K, V>> NodeInternal<K, V, S>
+{
+    fn new(key: K, left: Node<K, V, S>, right: Node<K, V, S>) -> NodeInternal<K, V, S>
     {
         let mut node = NodeInternal::new_empty();
         node.used = 2;
         node.keys[0] = key;
         node.total_len = right.len() + left.len();
+        node.summaries[0] = left.summary();
+        node.summaries[1] = right.summary();
         node.children[0] = left;
         node.children[1] = right;
         node
     }
 
-    fn new_empty() -> NodeInternal<K, V>
+    fn new_empty() -> NodeInternal<K, V, S>
     {
         NodeInternal {
             used: 0,
@@ -236,6 +674,17 @@ impl<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share> NodeInte
                        Empty, Empty, Empty, Empty,  // 32-35
                        Empty, Empty, Empty, Empty,  // 36-39
                        Empty, Empty],               // 40-41
+            summaries: [default(), default(), default(), default(),  // 0-3
+                        default(), default(), default(), default(),  // 4-7
+                        default(), default(), default(), default(),  // 8-11
+                        default(), default(), default(), default(),  // 12-15
+                        default(), default(), default(), default(),  // 16-19
+                        default(), default(), default(), default(),  // 20-23
+                        default(), default(), default(), default(),  // 24-27
+                        default(), default(), default(), default(),  // 28-31
+                        default(), default(), default(), default(),  // 32-35
+                        default(), default(), default(), default(),  // 36-39
+                        default(), default()],                       // 40-41
         }
     }
 
@@ -246,6 +695,7 @@ impl<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share> NodeInte
 
         match self.children[idx].insert(key, value) {
             InsertDone(updated) => {
+                self.refresh(idx);
                 if !updated {
                     self.total_len += 1;
                 }
@@ -256,6 +706,7 @@ impl<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share> NodeInte
                     Split(key, value)
                 } else {
                     let (right, split_key) = self.children[idx].split();
+                    let mut right_summary = right.summary();
                     let mut right = right;
                     let mut split_key = split_key;
 
@@ -268,13 +719,16 @@ impl<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share> NodeInte
 
                     for j in range(idx, self.used+1) {
                         mem::swap(&mut self.children[j], &mut right);
+                        mem::swap(&mut self.summaries[j], &mut right_summary);
                     }
+                    self.refresh(idx-1);
 
                     self.used += 1;
                     self.insert(key, value)
                 }
             },
             InsertUpdateLeft(left) => {
+                self.refresh(idx);
                 self.total_len += 1;
                 if idx != self.used {
                     self.keys[idx] = left;
@@ -293,6 +747,8 @@ impl<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share> NodeInte
             let (left, right) = self.children.mut_split_at(idx+1);
             if left[idx].rotate_left(&mut right[0]) { 
                 self.keys[idx] = left[idx].max_key();
+                self.refresh(idx);
+                self.refresh(idx+1);
                 return;
             }
         }
@@ -301,6 +757,8 @@ impl<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share> NodeInte
             let (left, right) = self.children.mut_split_at(idx);
             if right[0].rotate_right(&mut left[idx-1]) {
                 self.keys[idx-1] = left[idx-1].max_key();
+                self.refresh(idx-1);
+                self.refresh(idx);
                 return;
             }
         }
@@ -318,6 +776,7 @@ impl<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share> NodeInte
 
         self.children[insert].merge(child);
         self.keys[insert] = self.children[insert].max_key();
+        self.refresh(insert);
 
         if insert+1 != self.used-1  {
             self.keys[insert+1] = default();
@@ -325,6 +784,7 @@ impl<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share> NodeInte
 
         for i in range(insert+1, self.used-1) {
             self.children.swap(i, i+1);
+            self.summaries.swap(i, i+1);
         }
         for i in range(insert+1, self.used-2) {
             self.keys.swap(i, i+1);
@@ -338,6 +798,11 @@ impl<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share> NodeInte
         let (key, value, needs_merge) = self.children[idx].pop(key);
         let mut key = key;
 
+        if value.is_some() {
+            self.total_len -= 1;
+            self.refresh(idx);
+        }
+
         if self.used-1 != idx {
             match key {
                 Some(k) => {
@@ -352,10 +817,6 @@ impl<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share> NodeInte
             self.redist(idx);
         }
 
-        if value.is_some() {
-            self.total_len -= 1;
-        }
-
         (key, value, self.used < INTERNAL_SIZE / 2)
     }
 
@@ -424,12 +885,13 @@ impl<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share> NodeInte
 //    }
 
     #[inline(always)]
-    fn split(&mut self) -> (NodeInternal<K, V>, K)
+    fn split(&mut self) -> (NodeInternal<K, V, S>, K)
     {
         let mut right = NodeInternal::new_empty();
 
         for (dst, src) in range(INTERNAL_SIZE / 2, self.used).enumerate() {
             mem::swap(&mut right.children[dst], &mut self.children[src]);
+            mem::swap(&mut right.summaries[dst], &mut self.summaries[src]);
         }
 
         for (dst, src) in range(INTERNAL_SIZE / 2, self.used-1).enumerate() {
@@ -451,7 +913,7 @@ impl<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share> NodeInte
     }
 
     #[inline(always)]
-    fn rotate_left(&mut self, left: &mut NodeInternal<K, V>) -> bool
+    fn rotate_left(&mut self, left: &mut NodeInternal<K, V, S>) -> bool
     {
         if left.used > INTERNAL_SIZE / 2 {
             self.keys[self.used-1] = self.children[self.used-1].max_key();
@@ -460,8 +922,10 @@ impl<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share> NodeInte
             }
 
             mem::swap(&mut self.children[self.used], &mut left.children[0]);
+            mem::swap(&mut self.summaries[self.used], &mut left.summaries[0]);
             for i in range(0, left.used-1) {
                 left.children.swap(i, i+1);
+                left.summaries.swap(i, i+1);
             }
 
             let size = self.children[self.used].len();
@@ -476,7 +940,7 @@ impl<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share> NodeInte
     }
 
     #[inline(always)]
-    fn rotate_right(&mut self, right: &mut NodeInternal<K, V>) -> bool
+    fn rotate_right(&mut self, right: &mut NodeInternal<K, V, S>) -> bool
     {
         if right.used > INTERNAL_SIZE / 2 {
             let key = right.children[right.used-1].max_key();
@@ -489,8 +953,10 @@ impl<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share> NodeInte
             for i in range(0, self.used) {
                 let i = self.used - i;
                 self.children.swap(i, i-1);
+                self.summaries.swap(i, i-1);
             }
             mem::swap(&mut self.children[0], &mut right.children[right.used-1]);
+            mem::swap(&mut self.summaries[0], &mut right.summaries[right.used-1]);
 
             let size = self.children[0].len();
             right.used -= 1;
@@ -504,12 +970,13 @@ impl<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share> NodeInte
     }
 
     #[inline(always)]
-    fn merge(&mut self, right: &mut NodeInternal<K, V>)
+    fn merge(&mut self, right: &mut NodeInternal<K, V, S>)
     {
         self.keys[self.used-1] = self.children[self.used-1].max_key();
         for (src, dst) in range(self.used, self.used+right.used).enumerate() {
             mem::swap(&mut right.keys[src], &mut self.keys[dst]);
             mem::swap(&mut right.children[src], &mut self.children[dst]);
+            mem::swap(&mut right.summaries[src], &mut self.summaries[dst]);
         }
         self.total_len += right.total_len;
         self.used += right.used;
@@ -520,7 +987,44 @@ impl<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share> NodeInte
         self.children[self.used-1].max_key()
     }
 
-    fn iter<'a>(&'a self) -> NodeIterator<'a, K, V>
+    fn summary(&self) -> S
+    {
+        let mut summary: S = default();
+        for i in range(0, self.used) {
+            summary.combine(&self.summaries[i]);
+        }
+        summary
+    }
+
+    // recompute the summary of a child that was changed
+    #[inline(always)]
+    fn refresh(&mut self, idx: uint)
+    {
+        self.summaries[idx] = self.children[idx].summary();
+    }
+
+    // children from..to as a tree of their own that shares them with
+    // self, a single child is returned as it is
+    fn group(&self, from: uint, to: uint) -> Node<K, V, S>
+    {
+        if to - from == 1 {
+            return self.children[from].clone();
+        }
+
+        let mut node = NodeInternal::new_empty();
+        for j in range(from, to) {
+            if j != from {
+                node.keys[j-from-1] = self.keys[j-1].clone();
+            }
+            node.children[j-from] = self.children[j].clone();
+            node.summaries[j-from] = self.summaries[j].clone();
+            node.total_len += self.children[j].len();
+        }
+        node.used = to - from;
+        Internal(Arc::new(node))
+    }
+
+    fn iter<'a>(&'a self) -> NodeIterator<'a, K, V, S>
     {
         NodeIterator {
             idx: 0,
@@ -529,14 +1033,15 @@ impl<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share> NodeInte
     }
 }
 
-impl<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share> Clone for NodeInternal<K, V>
+impl<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share, S: Summary<K, V>> Clone for NodeInternal<K, V, S>
 {
-    fn clone(&self) -> NodeInternal<K, V>
+    fn clone(&self) -> NodeInternal<K, V, S>
     {
         let mut new = NodeInternal::new_empty();
 
         for i in range(0, self.used) {
             new.children[i] = self.children[i].clone();
+            new.summaries[i] = self.summaries[i].clone();
         }
 
         for i in range(0, self.used-1) {
@@ -768,6 +1273,16 @@ impl<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share> NodeLeaf
         self.keys[self.used-1].clone()
     }
 
+    // leaves are small enough to summarize when asked
+    fn summary<S: Summary<K, V>>(&self) -> S
+    {
+        let mut summary: S = default();
+        for i in range(0, self.used) {
+            summary.combine(&Summary::summarize(&self.keys[i], &self.values[i]));
+        }
+        summary
+    }
+
     #[inline(always)]
     fn iter<'a>(&'a self) -> LeafIterator<'a, K, V>
     {
@@ -806,24 +1321,7 @@ impl<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share> Map<K, V
     #[inline(always)]
     fn find<'a>(&'a self, key: &K) -> Option<&'a V>
     {
-        let mut target = &self.root;
-        let mut target_leaf: Option<&NodeLeaf<K, V>> = None;
-
-        while target_leaf.is_none() {
-            match *target {
-                Internal(ref node) => {
-                    target = &node.deref().children[node.deref().search(key)];
-                },
-                Leaf(ref leaf) => {
-                    target_leaf = Some(leaf.deref());
-                },
-                Empty => {
-                    return None;
-                },
-            };
-        }
-
-        target_leaf.unwrap().find(key)
+        self.root.find(key)
     }
 }
 
@@ -854,13 +1352,7 @@ impl<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share> MutableM
     #[inline(always)]
     fn pop(&mut self, key: &K) -> Option<V>
     {
-        match self.root.pop(key) {
-            (_, found, false) => found,
-            (_, found, true) => {
-                self.root.lift();
-                found
-            }
-        }
+        self.root.pop_root(key)
     }
 
     #[inline(always)]
@@ -872,35 +1364,9 @@ impl<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share> MutableM
     #[inline(always)]
     fn insert(&mut self, key: K, value: V) -> bool
     {
-        match self.root.insert(key, value) {
-            InsertDone(update) => update,
-            // if the left key is only updated
-            // on an insert, not an update so this
-            // can return false
-            InsertUpdateLeft(_) => false,
-            Split(key, value) => {
-                let (split_key, right) = match self.root {
-                    Leaf(ref mut leaf) => {
-                        let (right, key) = leaf.make_unique().split();
-                        (key, Leaf(Arc::new(right)))
-                    },
-                    Internal(ref mut node) => {
-                        let (right, key) = node.make_unique().split();
-                        (key, Internal(Arc::new(right)))
-
-                    }
-                    _ => fail!("this is impossible")
-                };
-                let mut left = Empty;
-
-                mem::swap(&mut self.root, &mut left);
-
-                self.root = Internal(Arc::new(NodeInternal::new(split_key, left, right)));
-                self.insert(key, value)
-            }
-        }
-    }
-}
+        self.root.insert_root(key, value)
+    }
+}
 
 impl<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share> Clone for BTreeMap<K, V>
 {
@@ -926,35 +1392,380 @@ impl<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share> BTreeMap
         }
     }
 
+    // build a map from entries with strictly increasing keys, the
+    // leaves are filled directly so nothing is searched or split. Any
+    // other input is inserted entry by entry, later entries win.
+    pub fn from_sorted_vec(entries: Vec<(K, V)>) -> BTreeMap<K, V>
+    {
+        BTreeMap {
+            root: Node::from_sorted_vec(entries)
+        }
+    }
+
     pub fn iter<'a>(&'a self) -> BTreeMapIterator<'a, K, V>
     {
-        let (leaf, stack) = match self.root {
-            Leaf(ref leaf) => {
-                (Some(leaf.deref().iter()), ~[])
+        self.root.iter()
+    }
+
+    // entries with lo <= key < hi, a missing bound leaves that side open
+    pub fn range<'a>(&'a self, lo: Option<&K>, hi: Option<&K>) -> BTreeMapIterator<'a, K, V>
+    {
+        let mut iter = self.iter();
+        match lo {
+            Some(lo) => iter.seek(lo),
+            None => ()
+        }
+        match hi {
+            Some(hi) => iter.limit(hi),
+            None => ()
+        }
+        iter
+    }
+
+    // number of entries with a key smaller than key
+    pub fn rank(&self, key: &K) -> uint
+    {
+        self.root.rank(key)
+    }
+
+    // number of entries with a key that is below, see BTreeMapIterator::seek_by
+    pub fn rank_by(&self, below: |&K| -> bool) -> uint
+    {
+        self.root.rank_by(below)
+    }
+
+    pub fn first<'a>(&'a self) -> Option<(&'a K, &'a V)>
+    {
+        self.root.first()
+    }
+
+    pub fn last<'a>(&'a self) -> Option<(&'a K, &'a V)>
+    {
+        self.root.last()
+    }
+
+    // the entry at position idx in key order, select(rank(k)) finds k
+    pub fn select<'a>(&'a self, idx: uint) -> Option<(&'a K, &'a V)>
+    {
+        self.root.select(idx)
+    }
+
+    // at most n disjoint subtrees covering the map in key order. The
+    // largest internal node is replaced by its children, neighbouring
+    // children are grouped into one node so there are never more than n
+    fn subtrees(&self, n: uint) -> Vec<Node<K, V, ()>>
+    {
+        let mut parts = Vec::new();
+        match self.root {
+            Empty => return parts,
+            ref root => parts.push(root.clone())
+        }
+
+        while parts.len() < n {
+            let mut largest = None;
+            let mut largest_len = 0;
+            for (i, part) in parts.iter().enumerate() {
+                match *part {
+                    Internal(ref node) if node.deref().total_len > largest_len => {
+                        largest = Some(i);
+                        largest_len = node.deref().total_len;
+                    },
+                    _ => ()
+                }
             }
-            Internal(ref node) => {
-                (None, ~[node.deref().iter()])
+
+            let idx = match largest {
+                Some(idx) => idx,
+                None => break
+            };
+
+            match parts.remove(idx).unwrap() {
+                Internal(ref node) => {
+                    let node = node.deref();
+                    let groups = cmp::min(n - parts.len(), node.used);
+
+                    // each group ends once it holds its share of the
+                    // entries, leaving a child for every group after it
+                    let mut from = 0;
+                    let mut below = 0;
+                    for g in range(1, groups + 1) {
+                        let mut to = from + 1;
+                        below += node.children[from].len();
+                        if g == groups {
+                            to = node.used;
+                        } else {
+                            let share = node.total_len * g / groups;
+                            while to < node.used - (groups - g) && below + node.children[to].len() <= share {
+                                below += node.children[to].len();
+                                to += 1;
+                            }
+                        }
+                        parts.insert(idx + g - 1, node.group(from, to));
+                        from = to;
+                    }
+                },
+                _ => fail!("only internal nodes are split")
+            }
+        }
+        parts
+    }
+
+    // iterators over at most n consecutive disjoint parts of the map,
+    // each covering whole subtrees, so they can be consumed independently
+    pub fn par_chunks<'a>(&'a self, n: uint) -> Vec<BTreeMapIterator<'a, K, V>>
+    {
+        let parts = self.subtrees(n);
+        let mut chunks = Vec::with_capacity(parts.len());
+        for (i, part) in parts.iter().enumerate() {
+            let mut iter = self.iter();
+            if i != 0 {
+                let (first, _) = part.first().unwrap();
+                iter.seek(first);
+            }
+            match parts.as_slice().get(i + 1) {
+                Some(next) => {
+                    let (first, _) = next.first().unwrap();
+                    iter.limit(first);
+                },
+                None => ()
+            }
+            chunks.push(iter);
+        }
+        chunks
+    }
+
+    // like par_chunks but each part is a map of its own that shares
+    // its nodes with self, so the parts can be sent to other tasks
+    pub fn split_subtrees(&self, n: uint) -> Vec<BTreeMap<K, V>>
+    {
+        self.subtrees(n).move_iter().map(|node| BTreeMap {root: node}).collect()
+    }
+}
+
+impl<K: Default+Clone+TotalOrd+Send+Share, V: Eq+Default+Clone+Send+Share> BTreeMap<K, V>
+{
+    // the changes that turn self into newer, in key order. Cheap when
+    // newer is a modified clone of self, since only the nodes that were
+    // copied on write are visited.
+    pub fn diff(&self, newer: &BTreeMap<K, V>) -> Vec<Change<K, V>>
+    {
+        let mut out = Vec::new();
+        self.root.diff(&newer.root, &mut out);
+        out
+    }
+}
+
+impl<K: SnapshotItem+Default+Clone+TotalOrd+Send+Share, V: SnapshotItem+Default+Clone+Send+Share> BTreeMap<K, V>
+{
+    // used by snapshot::write_snapshot
+    #[doc(hidden)]
+    pub fn write_nodes<W: Writer>(&self, w: &mut W) -> IoResult<()>
+    {
+        self.root.write_snapshot(w)
+    }
+
+    // used by snapshot::read_snapshot
+    #[doc(hidden)]
+    pub fn read_nodes<R: Reader>(r: &mut R) -> IoResult<BTreeMap<K, V>>
+    {
+        Ok(BTreeMap {
+            root: try!(Node::read_snapshot(r))
+        })
+    }
+}
+
+// an entry that differs between two versions of a map
+pub enum Change<K, V> {
+    Added(K, V),
+    Removed(K, V),
+    // key, old value, new value
+    Changed(K, V, V)
+}
+
+fn same<T>(a: &T, b: &T) -> bool
+{
+    a as *T == b as *T
+}
+
+impl<K: Default+Clone+TotalOrd+Send+Share, V: Eq+Default+Clone+Send+Share, S: Summary<K, V>> Node<K, V, S>
+{
+    // levels from this node down to the entries, a leaf is 1
+    fn height(&self) -> uint
+    {
+        let mut height = 0;
+        let mut node = self;
+        loop {
+            match *node {
+                Internal(ref n) => node = &n.deref().children[0],
+                Leaf(_) => return height + 1,
+                Empty => return height
+            }
+            height += 1;
+        }
+    }
+
+    // walks both versions front to back by key range. Subtrees still
+    // shared between them are skipped, a subtree whose keys fall before
+    // everything left on the other side is emitted whole, and anything
+    // else is opened, the taller side first, so shared nodes meet at the
+    // same height.
+    fn diff(&self, newer: &Node<K, V, S>, out: &mut Vec<Change<K, V>>)
+    {
+        let mut old = vec!(DiffNode(self, self.height()));
+        let mut new = vec!(DiffNode(newer, newer.height()));
+        loop {
+            let (a, b) = match (old.pop(), new.pop()) {
+                (None, None) => return,
+                (Some(a), None) => {
+                    a.emit(false, out);
+                    continue;
+                },
+                (None, Some(b)) => {
+                    b.emit(true, out);
+                    continue;
+                },
+                (Some(a), Some(b)) => (a, b)
+            };
+            if a.is_empty() {
+                new.push(b);
+                continue;
+            }
+            if b.is_empty() {
+                old.push(a);
+                continue;
+            }
+
+            match (a, b) {
+                (DiffEntry(k, x), DiffEntry(l, y)) => {
+                    match k.cmp(l) {
+                        Less => {
+                            out.push(Removed(k.clone(), x.clone()));
+                            new.push(b);
+                        },
+                        Greater => {
+                            out.push(Added(l.clone(), y.clone()));
+                            old.push(a);
+                        },
+                        Equal => if x != y {
+                            out.push(Changed(k.clone(), x.clone(), y.clone()));
+                        }
+                    }
+                    continue;
+                },
+                (DiffNode(&Leaf(ref x), _), DiffNode(&Leaf(ref y), _)) if same(x.deref(), y.deref()) => continue,
+                (DiffNode(&Internal(ref x), _), DiffNode(&Internal(ref y), _)) if same(x.deref(), y.deref()) => continue,
+                _ => ()
+            }
+
+            let (a_first, a_last) = a.bounds();
+            let (b_first, b_last) = b.bounds();
+            if a_last.cmp(b_first) == Less {
+                a.emit(false, out);
+                new.push(b);
+                continue;
+            }
+            if b_last.cmp(a_first) == Less {
+                b.emit(true, out);
+                old.push(a);
+                continue;
+            }
+
+            let (a_height, b_height) = (a.height(), b.height());
+            if a_height >= b_height {
+                a.open(&mut old);
+            } else {
+                old.push(a);
+            }
+            if b_height >= a_height {
+                b.open(&mut new);
+            } else {
+                new.push(b);
+            }
+        }
+    }
+}
+
+// a node still to be compared with its height, or an entry of an opened leaf
+enum DiffItem<'a, K, V, S>
+{
+    DiffNode(&'a Node<K, V, S>, uint),
+    DiffEntry(&'a K, &'a V)
+}
+
+impl<'a, K: Default+Clone+TotalOrd+Send+Share, V: Eq+Default+Clone+Send+Share, S: Summary<K, V>> DiffItem<'a, K, V, S>
+{
+    fn is_empty(&self) -> bool
+    {
+        match *self {
+            DiffNode(node, _) => node.len() == 0,
+            DiffEntry(..) => false
+        }
+    }
+
+    fn height(&self) -> uint
+    {
+        match *self {
+            DiffNode(_, height) => height,
+            DiffEntry(..) => 0
+        }
+    }
+
+    // the smallest and largest key under this item
+    fn bounds(&self) -> (&'a K, &'a K)
+    {
+        match *self {
+            DiffNode(node, _) => {
+                let (first, _) = node.first().unwrap();
+                let (last, _) = node.last().unwrap();
+                (first, last)
             },
-            Empty => (None, ~[])
-        };
-        BTreeMapIterator {
-            leaf: leaf,
-            stack: stack,
-            current: 0,
-            end: self.len()
+            DiffEntry(k, _) => (k, k)
+        }
+    }
+
+    // pushes the children or entries so the first one ends up on top
+    fn open(&self, stack: &mut Vec<DiffItem<'a, K, V, S>>)
+    {
+        match *self {
+            DiffNode(&Internal(ref n), height) => {
+                let n = n.deref();
+                for i in range(0, n.used).rev() {
+                    stack.push(DiffNode(&n.children[i], height - 1));
+                }
+            },
+            DiffNode(&Leaf(ref l), _) => {
+                let l = l.deref();
+                for i in range(0, l.used).rev() {
+                    stack.push(DiffEntry(&l.keys[i], &l.values[i]));
+                }
+            },
+            _ => ()
+        }
+    }
+
+    fn emit(&self, added: bool, out: &mut Vec<Change<K, V>>)
+    {
+        let change = |k: &K, v: &V| if added { Added(k.clone(), v.clone()) } else { Removed(k.clone(), v.clone()) };
+        match *self {
+            DiffNode(node, _) => {
+                for (k, v) in node.iter() {
+                    out.push(change(k, v));
+                }
+            },
+            DiffEntry(k, v) => out.push(change(k, v))
         }
     }
 }
 
-struct NodeIterator<'a, K, V>
+struct NodeIterator<'a, K, V, S>
 {
     idx: uint,
-    node: &'a NodeInternal<K, V>
+    node: &'a NodeInternal<K, V, S>
 }
 
-impl<'a, K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share> Iterator<NodeIteratorRes<'a,K,V>> for NodeIterator<'a, K, V>
+impl<'a, K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share, S: Summary<K, V>> Iterator<NodeIteratorRes<'a, K, V, S>> for NodeIterator<'a, K, V, S>
 {
-    fn next(&mut self) -> Option<NodeIteratorRes<'a,K,V>>
+    fn next(&mut self) -> Option<NodeIteratorRes<'a, K, V, S>>
     {
         if self.idx < self.node.used {
             let idx = self.idx;
@@ -994,26 +1805,29 @@ impl<'a, K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share> Iter
     }
 }
 
-pub struct BTreeMapIterator<'a, K, V>
+pub struct TreeIterator<'a, K, V, S>
 {
-    stack: ~[NodeIterator<'a, K, V>],
+    root: &'a Node<K, V, S>,
+    stack: ~[NodeIterator<'a, K, V, S>],
     leaf: Option<LeafIterator<'a, K, V>>,
     current: uint,
     end: uint
 }
 
-enum NodeIteratorRes<'a, K, V>
+pub type BTreeMapIterator<'a, K, V> = TreeIterator<'a, K, V, ()>;
+
+enum NodeIteratorRes<'a, K, V, S>
 {
-    InternalIter(NodeIterator<'a, K, V>),
+    InternalIter(NodeIterator<'a, K, V, S>),
     LeafIter(LeafIterator<'a, K, V>)
 }
 
-impl<'a, K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share> Iterator<(&'a K, &'a V)> for BTreeMapIterator<'a, K, V>
+impl<'a, K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share, S: Summary<K, V>> Iterator<(&'a K, &'a V)> for TreeIterator<'a, K, V, S>
 {
     #[inline(always)]
     fn next(&mut self) -> Option<(&'a K, &'a V)>
     {
-        if self.current == self.end {
+        if self.current >= self.end {
             return None;
         }
         loop {
@@ -1044,6 +1858,149 @@ impl<'a, K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share> Iter
     }
 }
 
+impl<'a, K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share, S: Summary<K, V>> TreeIterator<'a, K, V, S>
+{
+    // skip ahead to the first entry with a key >= key, this never
+    // moves the iterator backwards
+    pub fn seek(&mut self, key: &K)
+    {
+        if self.current == self.end {
+            return;
+        }
+
+        // the target is often close by, check the current leaf first
+        match self.leaf {
+            Some(ref mut l) if l.idx < l.leaf.used && key.cmp(&l.leaf.keys[l.leaf.used-1]) != Greater => {
+                let (_, idx) = l.leaf.search_key(key);
+                if idx > l.idx {
+                    self.current += idx - l.idx;
+                    l.idx = idx;
+                }
+                if self.current > self.end {
+                    self.current = self.end;
+                }
+                return;
+            },
+            _ => ()
+        }
+
+        let mut stack = ~[];
+        let mut leaf = None;
+        let mut rank = 0;
+        let mut node = self.root;
+        while leaf.is_none() {
+            match *node {
+                Internal(ref n) => {
+                    let n = n.deref();
+                    let idx = n.search(key);
+                    for i in range(0, idx) {
+                        rank += n.children[i].len();
+                    }
+                    stack.push(NodeIterator {idx: idx + 1, node: n});
+                    node = &n.children[idx];
+                },
+                Leaf(ref l) => {
+                    let l = l.deref();
+                    let (_, idx) = l.search_key(key);
+                    rank += idx;
+                    leaf = Some(LeafIterator {idx: idx, leaf: l});
+                },
+                Empty => return
+            }
+        }
+
+        if rank >= self.end {
+            self.current = self.end;
+        } else if rank > self.current {
+            self.stack = stack;
+            self.leaf = leaf;
+            self.current = rank;
+        }
+    }
+
+    // like seek, to the first entry whose key is not below. The keys
+    // below have to come before all others, e.g. the keys of a map of
+    // pairs with a given first element.
+    pub fn seek_by(&mut self, below: |&K| -> bool)
+    {
+        if self.current == self.end {
+            return;
+        }
+
+        let mut stack = ~[];
+        let mut leaf = None;
+        let mut rank = 0;
+        let mut node = self.root;
+        while leaf.is_none() {
+            match *node {
+                Internal(ref n) => {
+                    let n = n.deref();
+                    let mut idx = 0;
+                    while idx < n.used-1 && below(&n.keys[idx]) {
+                        rank += n.children[idx].len();
+                        idx += 1;
+                    }
+                    stack.push(NodeIterator {idx: idx + 1, node: n});
+                    node = &n.children[idx];
+                },
+                Leaf(ref l) => {
+                    let l = l.deref();
+                    let mut idx = 0;
+                    while idx < l.used && below(&l.keys[idx]) {
+                        idx += 1;
+                    }
+                    rank += idx;
+                    leaf = Some(LeafIterator {idx: idx, leaf: l});
+                },
+                Empty => return
+            }
+        }
+
+        if rank >= self.end {
+            self.current = self.end;
+        } else if rank > self.current {
+            self.stack = stack;
+            self.leaf = leaf;
+            self.current = rank;
+        }
+    }
+
+    // end the iteration at the first entry whose key is not below
+    pub fn limit_by(&mut self, below: |&K| -> bool)
+    {
+        let rank = self.root.rank_by(below);
+        if rank < self.end {
+            self.end = if rank > self.current { rank } else { self.current };
+        }
+    }
+
+    // end the iteration before the first entry with a key >= key, the
+    // position is found by a descent from the root
+    pub fn limit(&mut self, key: &K)
+    {
+        let rank = self.root.rank(key);
+        if rank < self.end {
+            self.end = if rank > self.current { rank } else { self.current };
+        }
+    }
+}
+
+impl<'a, K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share, S: Summary<K, V>> Seekable<&'a K> for TreeIterator<'a, K, V, S>
+{
+    fn seek(&mut self, key: &&'a K)
+    {
+        self.seek(*key)
+    }
+}
+
+impl<'a, K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share, S: Summary<K, V>> Limit<&'a K> for TreeIterator<'a, K, V, S>
+{
+    fn limit(&mut self, key: &&'a K)
+    {
+        self.limit(*key)
+    }
+}
+
 impl<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share> Default for BTreeMap<K, V>
 {
     fn default() -> BTreeMap<K, V>
@@ -1068,12 +2025,66 @@ impl<T: Default+Clone+TotalOrd+Send+Share> BTreeSet<T>
         BTreeSet{map: BTreeMap::new()}
     }
 
+    pub fn from_sorted_vec(values: Vec<T>) -> BTreeSet<T>
+    {
+        BTreeSet {
+            map: BTreeMap::from_sorted_vec(values.move_iter().map(|v| (v, ())).collect())
+        }
+    }
+
     pub fn iter<'a>(&'a self) -> BTreeSetIterator<'a, T>
     {
         BTreeSetIterator {
             mapiter: self.map.iter()
         }
     }
+
+    // values with lo <= value < hi, a missing bound leaves that side open
+    pub fn range<'a>(&'a self, lo: Option<&T>, hi: Option<&T>) -> BTreeSetIterator<'a, T>
+    {
+        BTreeSetIterator {
+            mapiter: self.map.range(lo, hi)
+        }
+    }
+
+    // (added, removed) values going from self to newer, see BTreeMap::diff
+    pub fn diff(&self, newer: &BTreeSet<T>) -> (Vec<T>, Vec<T>)
+    {
+        let mut added = Vec::new();
+        let mut removed = Vec::new();
+        for change in self.map.diff(&newer.map).move_iter() {
+            match change {
+                Added(v, _) => added.push(v),
+                Removed(v, _) => removed.push(v),
+                Changed(..) => ()
+            }
+        }
+        (added, removed)
+    }
+
+    pub fn union<'a>(&'a self, other: &'a BTreeSet<T>)
+        -> UnionIterator<&'a T, BTreeSetIterator<'a, T>, BTreeSetIterator<'a, T>>
+    {
+        union(self.iter(), other.iter())
+    }
+
+    pub fn intersection<'a>(&'a self, other: &'a BTreeSet<T>)
+        -> JoinSetIterator<BTreeSetIterator<'a, T>, BTreeSetIterator<'a, T>>
+    {
+        intersection(self.iter(), other.iter())
+    }
+
+    pub fn difference<'a>(&'a self, other: &'a BTreeSet<T>)
+        -> AntiJoinSetIterator<&'a T, BTreeSetIterator<'a, T>, BTreeSetIterator<'a, T>>
+    {
+        difference(self.iter(), other.iter())
+    }
+
+    pub fn symmetric_difference<'a>(&'a self, other: &'a BTreeSet<T>)
+        -> SymmetricDifferenceIterator<&'a T, BTreeSetIterator<'a, T>, BTreeSetIterator<'a, T>>
+    {
+        symmetric_difference(self.iter(), other.iter())
+    }
 }
 
 impl<T: Default+Clone+TotalOrd+Send+Share> Container for BTreeSet<T> {
@@ -1088,11 +2099,11 @@ impl<T: Default+Clone+TotalOrd+Send+Share> Set<T> for BTreeSet<T> {
     fn contains(&self, value: &T) -> bool { self.map.find(value).is_some() }
 
     fn is_disjoint(&self, other: &BTreeSet<T>) -> bool {
-        self.iter().all(|v| !other.contains(v))
+        self.intersection(other).next().is_none()
     }
 
     fn is_subset(&self, other: &BTreeSet<T>) -> bool {
-        self.iter().all(|v| other.contains(v))
+        self.len() <= other.len() && self.difference(other).next().is_none()
     }
 
     fn is_superset(&self, other: &BTreeSet<T>) -> bool {
@@ -1113,6 +2124,37 @@ impl<T: Default+Clone+TotalOrd+Send+Share> MutableSet<T> for BTreeSet<T>
     }
 }
 
+impl<'a, T: Default+Clone+TotalOrd+Send+Share> BTreeSetIterator<'a, T>
+{
+    // skip ahead to the first value >= value
+    pub fn seek(&mut self, value: &T)
+    {
+        self.mapiter.seek(value)
+    }
+
+    // stop before the first value >= value
+    pub fn limit(&mut self, value: &T)
+    {
+        self.mapiter.limit(value)
+    }
+}
+
+impl<'a, T: Default+Clone+TotalOrd+Send+Share> Seekable<&'a T> for BTreeSetIterator<'a, T>
+{
+    fn seek(&mut self, value: &&'a T)
+    {
+        self.mapiter.seek(*value)
+    }
+}
+
+impl<'a, T: Default+Clone+TotalOrd+Send+Share> Limit<&'a T> for BTreeSetIterator<'a, T>
+{
+    fn limit(&mut self, value: &&'a T)
+    {
+        self.mapiter.limit(*value)
+    }
+}
+
 impl<'a, K: Default+Clone+TotalOrd+Send+Share> Iterator<&'a K> for BTreeSetIterator<'a, K>
 {
     #[inline(always)]
@@ -1141,4 +2183,364 @@ impl<T: Default+Clone+TotalOrd+Send+Share> Default for BTreeSet<T>
     {
         BTreeSet::new()
     }
-}
\ No newline at end of file
+}
+// A map that keeps a Summary of its entries, so the summary of any key
+// range is found by descending the tree once, e.g. the total balance of
+// the accounts between two ids. Values are only changed by insert, an
+// update in place would not reach the summaries.
+pub struct SummaryMap<K, V, S> {
+    root: Node<K, V, S>
+}
+
+impl<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share, S: Summary<K, V>> SummaryMap<K, V, S>
+{
+    pub fn new() -> SummaryMap<K, V, S>
+    {
+        SummaryMap {
+            root: Empty
+        }
+    }
+
+    pub fn from_sorted_vec(entries: Vec<(K, V)>) -> SummaryMap<K, V, S>
+    {
+        SummaryMap {
+            root: Node::from_sorted_vec(entries)
+        }
+    }
+
+    // true if the key was already present, its value is replaced
+    pub fn insert(&mut self, key: K, value: V) -> bool
+    {
+        self.root.insert_root(key, value)
+    }
+
+    pub fn pop(&mut self, key: &K) -> Option<V>
+    {
+        self.root.pop_root(key)
+    }
+
+    pub fn remove(&mut self, key: &K) -> bool
+    {
+        self.pop(key).is_some()
+    }
+
+    pub fn iter<'a>(&'a self) -> TreeIterator<'a, K, V, S>
+    {
+        self.root.iter()
+    }
+
+    // entries with lo <= key < hi, a missing bound leaves that side open
+    pub fn range<'a>(&'a self, lo: Option<&K>, hi: Option<&K>) -> TreeIterator<'a, K, V, S>
+    {
+        let mut iter = self.iter();
+        match lo {
+            Some(lo) => iter.seek(lo),
+            None => ()
+        }
+        match hi {
+            Some(hi) => iter.limit(hi),
+            None => ()
+        }
+        iter
+    }
+
+    // number of entries with a key smaller than key
+    pub fn rank(&self, key: &K) -> uint
+    {
+        self.root.rank(key)
+    }
+
+    // the entry at position idx in key order
+    pub fn select<'a>(&'a self, idx: uint) -> Option<(&'a K, &'a V)>
+    {
+        self.root.select(idx)
+    }
+
+    // the summary of every entry
+    pub fn summary(&self) -> S
+    {
+        self.root.summary()
+    }
+
+    // the summary of the entries with lo <= key < hi, a missing bound
+    // leaves that side open
+    pub fn fold_range(&self, lo: Option<&K>, hi: Option<&K>) -> S
+    {
+        let mut acc: S = default();
+        match (lo, hi) {
+            (Some(lo), Some(hi)) if lo.cmp(hi) != Less => return acc,
+            _ => ()
+        }
+        self.root.fold_range(lo, hi, &mut acc);
+        acc
+    }
+}
+
+impl<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share, S: Summary<K, V>> Container for SummaryMap<K, V, S> {
+    fn len(&self) -> uint
+    {
+        self.root.len()
+    }
+}
+
+impl<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share, S: Summary<K, V>> Map<K, V> for SummaryMap<K, V, S> {
+    fn find<'a>(&'a self, key: &K) -> Option<&'a V>
+    {
+        self.root.find(key)
+    }
+}
+
+impl<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share, S: Summary<K, V>> Mutable for SummaryMap<K, V, S> {
+    fn clear(&mut self)
+    {
+        self.root = Empty;
+    }
+}
+
+impl<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share, S: Summary<K, V>> Clone for SummaryMap<K, V, S>
+{
+    fn clone(&self) -> SummaryMap<K, V, S>
+    {
+        SummaryMap {
+            root: self.root.clone()
+        }
+    }
+}
+
+impl<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share, S: Summary<K, V>> Default for SummaryMap<K, V, S>
+{
+    fn default() -> SummaryMap<K, V, S>
+    {
+        SummaryMap::new()
+    }
+}
+
+// the largest end of the intervals below a node
+#[deriving(Clone, Default)]
+pub struct MaxEnd<K> {
+    end: Option<K>
+}
+
+impl<K: TotalOrd> MaxEnd<K>
+{
+    fn ends_after(&self, point: &K) -> bool
+    {
+        match self.end {
+            Some(ref end) => end.cmp(point) == Greater,
+            None => false
+        }
+    }
+}
+
+impl<K: Default+Clone+TotalOrd+Send+Share, V> Summary<(K, K), V> for MaxEnd<K>
+{
+    fn summarize(key: &(K, K), _: &V) -> MaxEnd<K>
+    {
+        let &(_, ref end) = key;
+        MaxEnd {end: Some(end.clone())}
+    }
+
+    fn combine(&mut self, other: &MaxEnd<K>)
+    {
+        let larger = match (&self.end, &other.end) {
+            (_, &None) => false,
+            (&None, &Some(_)) => true,
+            (&Some(ref a), &Some(ref b)) => b.cmp(a) == Greater
+        };
+        if larger {
+            self.end = other.end.clone();
+        }
+    }
+}
+
+// Values keyed by half-open intervals [start, end), ordered by start and
+// then by end. Internal nodes keep the largest end found below each
+// child, so queries skip the subtrees whose intervals all end too
+// early. Clones share their nodes like BTreeMap does.
+pub struct IntervalMap<K, V> {
+    root: Node<(K, K), V, MaxEnd<K>>
+}
+
+pub type IntervalMapIterator<'a, K, V> = TreeIterator<'a, (K, K), V, MaxEnd<K>>;
+
+impl<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share> IntervalMap<K, V>
+{
+    pub fn new() -> IntervalMap<K, V>
+    {
+        IntervalMap {
+            root: Empty
+        }
+    }
+
+    // true if the interval was already present, its value is replaced.
+    // An empty interval is not stored.
+    pub fn insert(&mut self, start: K, end: K, value: V) -> bool
+    {
+        if start.cmp(&end) != Less {
+            return false;
+        }
+        self.root.insert_root((start, end), value)
+    }
+
+    pub fn find<'a>(&'a self, start: &K, end: &K) -> Option<&'a V>
+    {
+        self.root.find(&(start.clone(), end.clone()))
+    }
+
+    pub fn remove(&mut self, start: &K, end: &K) -> Option<V>
+    {
+        self.root.pop_root(&(start.clone(), end.clone()))
+    }
+
+    // every interval in (start, end) order
+    pub fn iter<'a>(&'a self) -> IntervalMapIterator<'a, K, V>
+    {
+        self.root.iter()
+    }
+
+    // the intervals with start <= point < end, ordered by start
+    pub fn containing<'a>(&'a self, point: &K) -> IntervalIterator<'a, K, V>
+    {
+        IntervalIterator::new(&self.root, point.clone(), point.clone(), true)
+    }
+
+    // the intervals sharing at least one point with [lo, hi), ordered
+    // by start
+    pub fn overlapping<'a>(&'a self, lo: &K, hi: &K) -> IntervalIterator<'a, K, V>
+    {
+        let mut iter = IntervalIterator::new(&self.root, lo.clone(), hi.clone(), false);
+        // an empty range overlaps nothing
+        if lo.cmp(hi) != Less {
+            iter.stack.clear();
+            iter.leaf = None;
+        }
+        iter
+    }
+}
+
+impl<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share> Container for IntervalMap<K, V> {
+    fn len(&self) -> uint
+    {
+        self.root.len()
+    }
+}
+
+impl<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share> Mutable for IntervalMap<K, V> {
+    fn clear(&mut self)
+    {
+        self.root = Empty;
+    }
+}
+
+impl<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share> Clone for IntervalMap<K, V>
+{
+    fn clone(&self) -> IntervalMap<K, V>
+    {
+        IntervalMap {
+            root: self.root.clone()
+        }
+    }
+}
+
+impl<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share> Default for IntervalMap<K, V>
+{
+    fn default() -> IntervalMap<K, V>
+    {
+        IntervalMap::new()
+    }
+}
+
+pub struct IntervalIterator<'a, K, V> {
+    stack: Vec<(&'a NodeInternal<(K, K), V, MaxEnd<K>>, uint)>,
+    leaf: Option<LeafIterator<'a, (K, K), V>>,
+    // the intervals wanted end after this
+    after: K,
+    // and start before it, or at it when closed
+    before: K,
+    closed: bool
+}
+
+impl<'a, K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share> IntervalIterator<'a, K, V>
+{
+    fn new(root: &'a Node<(K, K), V, MaxEnd<K>>, after: K, before: K, closed: bool) -> IntervalIterator<'a, K, V>
+    {
+        let mut iter = IntervalIterator {
+            stack: Vec::new(),
+            leaf: None,
+            after: after,
+            before: before,
+            closed: closed
+        };
+        iter.descend(root);
+        iter
+    }
+
+    fn starts_in(&self, start: &K) -> bool
+    {
+        match start.cmp(&self.before) {
+            Less => true,
+            Equal => self.closed,
+            Greater => false
+        }
+    }
+
+    fn descend(&mut self, node: &'a Node<(K, K), V, MaxEnd<K>>)
+    {
+        match *node {
+            Internal(ref n) => self.stack.push((n.deref(), 0)),
+            Leaf(ref l) => self.leaf = Some(l.deref().iter()),
+            Empty => ()
+        }
+    }
+}
+
+impl<'a, K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share> Iterator<(&'a K, &'a K, &'a V)> for IntervalIterator<'a, K, V>
+{
+    fn next(&mut self) -> Option<(&'a K, &'a K, &'a V)>
+    {
+        loop {
+            let next = match self.leaf {
+                Some(ref mut l) => l.next(),
+                None => None
+            };
+
+            match next {
+                Some((&(ref start, ref end), value)) => {
+                    // everything after this starts too late as well
+                    if !self.starts_in(start) {
+                        self.leaf = None;
+                        self.stack.clear();
+                        return None;
+                    }
+                    if end.cmp(&self.after) == Greater {
+                        return Some((start, end, value));
+                    }
+                    continue;
+                },
+                None => self.leaf = None
+            }
+
+            let (node, idx) = match self.stack.pop() {
+                Some(top) => top,
+                None => return None
+            };
+            if idx == node.used {
+                continue;
+            }
+
+            // child idx only holds keys above keys[idx-1]
+            if idx != 0 {
+                let &(ref start, _) = &node.keys[idx-1];
+                if !self.starts_in(start) {
+                    self.stack.clear();
+                    return None;
+                }
+            }
+            self.stack.push((node, idx + 1));
+            // skip children whose intervals all end too early
+            if node.summaries[idx].ends_after(&self.after) {
+                self.descend(&node.children[idx]);
+            }
+        }
+    }
+}
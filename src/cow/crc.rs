@@ -0,0 +1,38 @@
+// CRC-32 (IEEE 802.3), used to detect corrupt snapshots and log records
+
+static POLYNOMIAL: u32 = 0xedb88320;
+
+pub struct Crc32 {
+    state: u32
+}
+
+impl Crc32
+{
+    pub fn new() -> Crc32
+    {
+        Crc32 {state: 0xffffffff}
+    }
+
+    pub fn update(&mut self, data: &[u8])
+    {
+        for &byte in data.iter() {
+            self.state ^= byte as u32;
+            for _ in range(0, 8) {
+                let mask = 0 - (self.state & 1);
+                self.state = (self.state >> 1) ^ (POLYNOMIAL & mask);
+            }
+        }
+    }
+
+    pub fn finish(&self) -> u32
+    {
+        !self.state
+    }
+}
+
+pub fn crc32(data: &[u8]) -> u32
+{
+    let mut crc = Crc32::new();
+    crc.update(data);
+    crc.finish()
+}
@@ -0,0 +1,147 @@
+use std::cell::RefCell;
+use std::default::Default;
+use std::mem;
+
+use btree::{BTreeSet, BTreeSetIterator};
+use join::join_maps_multi;
+
+// Semi-naive evaluation of rules over relations stored in BTreeSets.
+//
+// A Variable holds a relation that grows while the rules are evaluated.
+// Each round the rules only look at what was added in the round before,
+// so nothing is derived from the same facts twice. A round is
+//
+//   while path.changed() | edge.changed() {
+//       path.from_join(&path, &edge, |_, &x, &z| (z, x));
+//   }
+//
+// where changed() makes the tuples produced in the last round visible
+// and reports whether any of them were new. Every variable has to be
+// asked each round, hence | instead of ||.
+
+pub struct Variable<T> {
+    // every tuple up to the last round
+    stable: BTreeSet<T>,
+    // tuples that first appeared in the last round
+    recent: BTreeSet<T>,
+    // stable and recent together
+    all: BTreeSet<T>,
+    // produced by rules this round
+    pending: RefCell<Vec<T>>
+}
+
+impl<T: Default+Clone+TotalOrd+Send+Share> Variable<T>
+{
+    pub fn new() -> Variable<T>
+    {
+        Variable {
+            stable: BTreeSet::new(),
+            recent: BTreeSet::new(),
+            all: BTreeSet::new(),
+            pending: RefCell::new(Vec::new())
+        }
+    }
+
+    // a variable starting out with the tuples of set
+    pub fn from_set(set: &BTreeSet<T>) -> Variable<T>
+    {
+        let var = Variable::new();
+        for tuple in set.iter() {
+            var.insert(tuple.clone());
+        }
+        var
+    }
+
+    pub fn insert(&self, tuple: T)
+    {
+        self.pending.borrow_mut().push(tuple);
+    }
+
+    // add the pending tuples, the ones that were not known yet become
+    // recent. The old and new relation share all untouched nodes so the
+    // delta is found with a snapshot diff.
+    pub fn changed(&mut self) -> bool
+    {
+        self.stable = self.all.clone();
+        let pending = mem::replace(&mut *self.pending.borrow_mut(), Vec::new());
+        for tuple in pending.move_iter() {
+            self.all.insert(tuple);
+        }
+
+        let (added, _) = self.stable.diff(&self.all);
+        self.recent = BTreeSet::from_sorted_vec(added);
+        self.recent.len() != 0
+    }
+
+    pub fn all<'a>(&'a self) -> &'a BTreeSet<T>
+    {
+        &self.all
+    }
+
+    pub fn recent<'a>(&'a self) -> &'a BTreeSet<T>
+    {
+        &self.recent
+    }
+
+    // the relation once the rules have reached a fixpoint
+    pub fn complete(mut self) -> BTreeSet<T>
+    {
+        while self.changed() {}
+        self.all
+    }
+
+    // this(logic(k, a, b)) :- a(k, a), b(k, b)
+    pub fn from_join<K: Default+Clone+TotalOrd+Send+Share, A: Default+Clone+TotalOrd+Send+Share, B: Default+Clone+TotalOrd+Send+Share>
+        (&self, a: &Variable<(K, A)>, b: &Variable<(K, B)>, logic: |&K, &A, &B| -> T)
+    {
+        let mut out = self.pending.borrow_mut();
+        // new facts need at least one side to be recent
+        for (k, (x, y)) in join_maps_multi(pairs(&a.recent), pairs(&b.all)) {
+            out.push(logic(k, x, y));
+        }
+        for (k, (x, y)) in join_maps_multi(pairs(&a.stable), pairs(&b.recent)) {
+            out.push(logic(k, x, y));
+        }
+    }
+
+    // this(logic(s)) :- input(s)
+    pub fn from_map<S: Default+Clone+TotalOrd+Send+Share>(&self, input: &Variable<S>, logic: |&S| -> T)
+    {
+        let mut out = self.pending.borrow_mut();
+        for s in input.recent.iter() {
+            out.push(logic(s));
+        }
+    }
+
+    // this(t) :- input(t), keep(t)
+    pub fn from_filter(&self, input: &Variable<T>, keep: |&T| -> bool)
+    {
+        let mut out = self.pending.borrow_mut();
+        for t in input.recent.iter() {
+            if keep(t) {
+                out.push(t.clone());
+            }
+        }
+    }
+}
+
+// a set of pairs seen as a sorted (key, data) stream for the joins
+struct Pairs<'a, K, A> {
+    iter: BTreeSetIterator<'a, (K, A)>
+}
+
+fn pairs<'a, K: Default+Clone+TotalOrd+Send+Share, A: Default+Clone+TotalOrd+Send+Share>(set: &'a BTreeSet<(K, A)>) -> Pairs<'a, K, A>
+{
+    Pairs {iter: set.iter()}
+}
+
+impl<'a, K: Default+Clone+TotalOrd+Send+Share, A: Default+Clone+TotalOrd+Send+Share> Iterator<(&'a K, &'a A)> for Pairs<'a, K, A>
+{
+    fn next(&mut self) -> Option<(&'a K, &'a A)>
+    {
+        match self.iter.next() {
+            Some(&(ref k, ref a)) => Some((k, a)),
+            None => None
+        }
+    }
+}
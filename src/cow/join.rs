@@ -1,4 +1,16 @@
+use std::iter::Peekable;
 
+// Iterators over sorted streams that can skip ahead to the first item
+// whose key is >= key without visiting everything in between.
+pub trait Seekable<KEY> {
+    fn seek(&mut self, key: &KEY);
+}
+
+// Iterators that can be told to end before the first item whose key is
+// >= key.
+pub trait Limit<KEY> {
+    fn limit(&mut self, key: &KEY);
+}
 
 pub struct JoinMapIterator<A, B> {a: A, b: B}
 
@@ -139,4 +151,1428 @@ impl<KEY: TotalOrd, DATA, SetIter: Iterator<KEY>, MapIter: Iterator<(KEY, DATA)>
             }
         }
     }
-}
\ No newline at end of file
+}
+
+// Shared by the outer joins, left and right say which of the one sided
+// rows are produced. Rows only in A are (key, (Some, None)) and rows only
+// in B are (key, (None, Some)).
+pub struct OuterJoinMapIterator<KEY, DATA_A, DATA_B, A, B> {
+    a: Peekable<(KEY, DATA_A), A>,
+    b: Peekable<(KEY, DATA_B), B>,
+    left: bool,
+    right: bool
+}
+
+pub fn left_join_maps<KEY: TotalOrd, DATA_A, DATA_B, IterA: Iterator<(KEY, DATA_A)>, IterB: Iterator<(KEY, DATA_B)>>
+    (a: IterA, b: IterB) -> OuterJoinMapIterator<KEY, DATA_A, DATA_B, IterA, IterB>
+{
+    OuterJoinMapIterator {a: a.peekable(), b: b.peekable(), left: true, right: false}
+}
+
+pub fn right_join_maps<KEY: TotalOrd, DATA_A, DATA_B, IterA: Iterator<(KEY, DATA_A)>, IterB: Iterator<(KEY, DATA_B)>>
+    (a: IterA, b: IterB) -> OuterJoinMapIterator<KEY, DATA_A, DATA_B, IterA, IterB>
+{
+    OuterJoinMapIterator {a: a.peekable(), b: b.peekable(), left: false, right: true}
+}
+
+pub fn full_outer_join_maps<KEY: TotalOrd, DATA_A, DATA_B, IterA: Iterator<(KEY, DATA_A)>, IterB: Iterator<(KEY, DATA_B)>>
+    (a: IterA, b: IterB) -> OuterJoinMapIterator<KEY, DATA_A, DATA_B, IterA, IterB>
+{
+    OuterJoinMapIterator {a: a.peekable(), b: b.peekable(), left: true, right: true}
+}
+
+impl<KEY: TotalOrd, DATA_A, DATA_B, IterA: Iterator<(KEY, DATA_A)>, IterB: Iterator<(KEY, DATA_B)>>
+    Iterator<(KEY, (Option<DATA_A>, Option<DATA_B>))> for OuterJoinMapIterator<KEY, DATA_A, DATA_B, IterA, IterB>
+{
+    #[inline(never)]
+    fn next(&mut self) -> Option<(KEY, (Option<DATA_A>, Option<DATA_B>))>
+    {
+        loop {
+            let ord = match (self.a.peek(), self.b.peek()) {
+                (Some(&(ref key_a, _)), Some(&(ref key_b, _))) => key_a.cmp(key_b),
+                (Some(_), None) if self.left => Less,
+                (None, Some(_)) if self.right => Greater,
+                (_, _) => return None
+            };
+
+            match ord {
+                Less => {
+                    let (key, data) = self.a.next().unwrap();
+                    if self.left {
+                        return Some((key, (Some(data), None)));
+                    }
+                },
+                Equal => {
+                    let (key, data_a) = self.a.next().unwrap();
+                    let (_, data_b) = self.b.next().unwrap();
+                    return Some((key, (Some(data_a), Some(data_b))));
+                },
+                Greater => {
+                    let (key, data) = self.b.next().unwrap();
+                    if self.right {
+                        return Some((key, (None, Some(data))));
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub struct SemiJoinMapIterator<A, B> {a: A, b: B}
+
+// entries of a whose key is also in b
+pub fn semi_join_maps<KEY: TotalOrd, DATA_A, DATA_B, IterA: Iterator<(KEY, DATA_A)>, IterB: Iterator<(KEY, DATA_B)>>
+    (a: IterA, b: IterB) -> SemiJoinMapIterator<IterA, IterB>
+{
+    SemiJoinMapIterator {a: a, b: b}
+}
+
+impl<KEY: TotalOrd, DATA_A, DATA_B, IterA: Iterator<(KEY, DATA_A)>, IterB: Iterator<(KEY, DATA_B)>>
+    Iterator<(KEY, DATA_A)> for SemiJoinMapIterator<IterA, IterB>
+{
+    #[inline(never)]
+    fn next(&mut self) -> Option<(KEY, DATA_A)>
+    {
+        let (mut key_a, mut data_a) = match self.a.next() {
+            None => return None,
+            Some((key, data)) => (key, data)
+        };
+
+        let mut key_b = match self.b.next() {
+            None => return None,
+            Some((key, _)) => key
+        };
+
+        loop {
+            match key_a.cmp(&key_b) {
+                Less => {
+                    match self.a.next() {
+                        None => return None,
+                        Some((key, data)) => {
+                            key_a = key;
+                            data_a = data;
+                        }
+                    };
+                },
+                Equal => return Some((key_a, data_a)),
+                Greater => {
+                    match self.b.next() {
+                        None => return None,
+                        Some((key, _)) => { key_b = key; }
+                    };
+                }
+            }
+        }
+    }
+}
+
+// The anti joins only ever look ahead on the stream being subtracted,
+// every item of the other stream is either returned or dropped.
+
+pub struct AntiJoinMapIterator<KEY, DATA_B, A, B> {a: A, b: Peekable<(KEY, DATA_B), B>}
+
+// entries of a whose key is not in b
+pub fn anti_join_maps<KEY: TotalOrd, DATA_A, DATA_B, IterA: Iterator<(KEY, DATA_A)>, IterB: Iterator<(KEY, DATA_B)>>
+    (a: IterA, b: IterB) -> AntiJoinMapIterator<KEY, DATA_B, IterA, IterB>
+{
+    AntiJoinMapIterator {a: a, b: b.peekable()}
+}
+
+impl<KEY: TotalOrd, DATA_A, DATA_B, IterA: Iterator<(KEY, DATA_A)>, IterB: Iterator<(KEY, DATA_B)>>
+    Iterator<(KEY, DATA_A)> for AntiJoinMapIterator<KEY, DATA_B, IterA, IterB>
+{
+    #[inline(never)]
+    fn next(&mut self) -> Option<(KEY, DATA_A)>
+    {
+        'next: loop {
+            let (key, data) = match self.a.next() {
+                None => return None,
+                Some((key, data)) => (key, data)
+            };
+
+            loop {
+                let ord = match self.b.peek() {
+                    Some(&(ref key_b, _)) => key_b.cmp(&key),
+                    None => Greater
+                };
+
+                match ord {
+                    Less => { self.b.next(); },
+                    Equal => continue 'next,
+                    Greater => return Some((key, data))
+                }
+            }
+        }
+    }
+}
+
+pub struct AntiJoinSetIterator<KEY, A, B> {a: A, b: Peekable<KEY, B>}
+
+// keys of a that are not in b
+pub fn anti_join_sets<KEY: TotalOrd, IterA: Iterator<KEY>, IterB: Iterator<KEY>>
+    (a: IterA, b: IterB) -> AntiJoinSetIterator<KEY, IterA, IterB>
+{
+    AntiJoinSetIterator {a: a, b: b.peekable()}
+}
+
+impl<KEY: TotalOrd, IterA: Iterator<KEY>, IterB: Iterator<KEY>>
+    Iterator<KEY> for AntiJoinSetIterator<KEY, IterA, IterB>
+{
+    #[inline(never)]
+    fn next(&mut self) -> Option<KEY>
+    {
+        'next: loop {
+            let key = match self.a.next() {
+                None => return None,
+                Some(key) => key
+            };
+
+            loop {
+                let ord = match self.b.peek() {
+                    Some(key_b) => key_b.cmp(&key),
+                    None => Greater
+                };
+
+                match ord {
+                    Less => { self.b.next(); },
+                    Equal => continue 'next,
+                    Greater => return Some(key)
+                }
+            }
+        }
+    }
+}
+
+pub struct AntiJoinMapSetIterator<KEY, MAP, SET> {map: MAP, set: Peekable<KEY, SET>}
+
+// entries of map whose key is not in set
+pub fn anti_join_map_set<KEY: TotalOrd, DATA, MapIter: Iterator<(KEY, DATA)>, SetIter: Iterator<KEY>>
+    (map: MapIter, set: SetIter) -> AntiJoinMapSetIterator<KEY, MapIter, SetIter>
+{
+    AntiJoinMapSetIterator {map: map, set: set.peekable()}
+}
+
+impl<KEY: TotalOrd, DATA, MapIter: Iterator<(KEY, DATA)>, SetIter: Iterator<KEY>>
+    Iterator<(KEY, DATA)> for AntiJoinMapSetIterator<KEY, MapIter, SetIter>
+{
+    #[inline(never)]
+    fn next(&mut self) -> Option<(KEY, DATA)>
+    {
+        'next: loop {
+            let (key, data) = match self.map.next() {
+                None => return None,
+                Some((key, data)) => (key, data)
+            };
+
+            loop {
+                let ord = match self.set.peek() {
+                    Some(key_set) => key_set.cmp(&key),
+                    None => Greater
+                };
+
+                match ord {
+                    Less => { self.set.next(); },
+                    Equal => continue 'next,
+                    Greater => return Some((key, data))
+                }
+            }
+        }
+    }
+}
+
+// The seek joins behave like the joins above but let the lagging side
+// seek to the leading key instead of stepping through it, so joining a
+// small stream against a large one costs O(m log n) instead of O(m + n).
+
+pub struct SeekJoinMapIterator<A, B> {a: A, b: B}
+
+pub fn seek_join_maps<KEY: TotalOrd, DATA_A, DATA_B, IterA: Iterator<(KEY, DATA_A)>+Seekable<KEY>, IterB: Iterator<(KEY, DATA_B)>+Seekable<KEY>>
+    (a: IterA, b: IterB) -> SeekJoinMapIterator<IterA, IterB>
+{
+    SeekJoinMapIterator {a: a, b: b}
+}
+
+impl<KEY: TotalOrd, DATA_A, DATA_B, IterA: Iterator<(KEY, DATA_A)>+Seekable<KEY>, IterB: Iterator<(KEY, DATA_B)>+Seekable<KEY>>
+    Iterator<(KEY, (DATA_A, DATA_B))> for SeekJoinMapIterator<IterA, IterB>
+{
+    #[inline(never)]
+    fn next(&mut self) -> Option<(KEY, (DATA_A, DATA_B))>
+    {
+        let (mut key_a, mut data_a) = match self.a.next() {
+            None => return None,
+            Some((key, data)) => (key, data)
+        };
+
+        let (mut key_b, mut data_b) = match self.b.next() {
+            None => return None,
+            Some((key, data)) => (key, data)
+        };
+
+        loop {
+            match key_a.cmp(&key_b) {
+                Less => {
+                    self.a.seek(&key_b);
+                    match self.a.next() {
+                        None => return None,
+                        Some((key, data)) => {
+                            key_a = key;
+                            data_a = data;
+                        }
+                    };
+                },
+                Equal => return Some((key_a, (data_a, data_b))),
+                Greater => {
+                    self.b.seek(&key_a);
+                    match self.b.next() {
+                        None => return None,
+                        Some((key, data)) => {
+                            key_b = key;
+                            data_b = data;
+                        }
+                    };
+                }
+            }
+        }
+    }
+}
+
+pub struct SeekJoinSetIterator<A, B> {a: A, b: B}
+
+pub fn seek_join_sets<KEY: TotalOrd, IterA: Iterator<KEY>+Seekable<KEY>, IterB: Iterator<KEY>+Seekable<KEY>>
+    (a: IterA, b: IterB) -> SeekJoinSetIterator<IterA, IterB>
+{
+    SeekJoinSetIterator {a: a, b: b}
+}
+
+impl<KEY: TotalOrd, IterA: Iterator<KEY>+Seekable<KEY>, IterB: Iterator<KEY>+Seekable<KEY>>
+    Iterator<KEY> for SeekJoinSetIterator<IterA, IterB>
+{
+    #[inline(never)]
+    fn next(&mut self) -> Option<KEY>
+    {
+        let mut key_a = match self.a.next() {
+            None => return None,
+            Some(key) => key
+        };
+
+        let mut key_b = match self.b.next() {
+            None => return None,
+            Some(key) => key
+        };
+
+        loop {
+            match key_a.cmp(&key_b) {
+                Less => {
+                    self.a.seek(&key_b);
+                    match self.a.next() {
+                        None => return None,
+                        Some(key) => { key_a = key; }
+                    };
+                },
+                Equal => return Some(key_a),
+                Greater => {
+                    self.b.seek(&key_a);
+                    match self.b.next() {
+                        None => return None,
+                        Some(key) => { key_b = key; }
+                    };
+                }
+            }
+        }
+    }
+}
+
+pub struct SeekJoinMapSetIterator<A, B> {set: A, map: B}
+
+pub fn seek_join_set_to_map<KEY: TotalOrd, DATA, SetIter: Iterator<KEY>+Seekable<KEY>, MapIter: Iterator<(KEY, DATA)>+Seekable<KEY>>
+    (set: SetIter, map: MapIter) -> SeekJoinMapSetIterator<SetIter, MapIter>
+{
+    SeekJoinMapSetIterator {set: set, map: map}
+}
+
+impl<KEY: TotalOrd, DATA, SetIter: Iterator<KEY>+Seekable<KEY>, MapIter: Iterator<(KEY, DATA)>+Seekable<KEY>>
+    Iterator<(KEY, DATA)> for SeekJoinMapSetIterator<SetIter, MapIter>
+{
+    #[inline(never)]
+    fn next(&mut self) -> Option<(KEY, DATA)>
+    {
+        let mut key_set = match self.set.next() {
+            None => return None,
+            Some(key) => key
+        };
+
+        let (mut key_map, mut data) = match self.map.next() {
+            None => return None,
+            Some((key, data)) => (key, data)
+        };
+
+        loop {
+            match key_set.cmp(&key_map) {
+                Less => {
+                    self.set.seek(&key_map);
+                    match self.set.next() {
+                        None => return None,
+                        Some(key) => { key_set = key; }
+                    };
+                },
+                Equal => return Some((key_set, data)),
+                Greater => {
+                    self.map.seek(&key_set);
+                    match self.map.next() {
+                        None => return None,
+                        Some((key, d)) => {
+                            key_map = key;
+                            data = d;
+                        }
+                    };
+                }
+            }
+        }
+    }
+}
+
+// Adaptors that make any sorted stream Seekable by stepping through it,
+// for inputs that are not backed by a tree.
+
+pub struct LinearSeekSet<KEY, I> {iter: Peekable<KEY, I>, hi: Option<KEY>}
+
+pub fn linear_seek_set<KEY: TotalOrd, I: Iterator<KEY>>(iter: I) -> LinearSeekSet<KEY, I>
+{
+    LinearSeekSet {iter: iter.peekable(), hi: None}
+}
+
+impl<KEY: TotalOrd, I: Iterator<KEY>> Iterator<KEY> for LinearSeekSet<KEY, I>
+{
+    fn next(&mut self) -> Option<KEY>
+    {
+        let past = match (&self.hi, self.iter.peek()) {
+            (&Some(ref hi), Some(k)) => k.cmp(hi) != Less,
+            _ => false
+        };
+        if past {
+            return None;
+        }
+        self.iter.next()
+    }
+}
+
+impl<KEY: TotalOrd, I: Iterator<KEY>> Seekable<KEY> for LinearSeekSet<KEY, I>
+{
+    fn seek(&mut self, key: &KEY)
+    {
+        loop {
+            match self.iter.peek() {
+                Some(k) if k.cmp(key) == Less => (),
+                _ => return
+            }
+            self.iter.next();
+        }
+    }
+}
+
+pub struct LinearSeekMap<KEY, DATA, I> {iter: Peekable<(KEY, DATA), I>, hi: Option<KEY>}
+
+pub fn linear_seek_map<KEY: TotalOrd, DATA, I: Iterator<(KEY, DATA)>>(iter: I) -> LinearSeekMap<KEY, DATA, I>
+{
+    LinearSeekMap {iter: iter.peekable(), hi: None}
+}
+
+impl<KEY: TotalOrd, DATA, I: Iterator<(KEY, DATA)>> Iterator<(KEY, DATA)> for LinearSeekMap<KEY, DATA, I>
+{
+    fn next(&mut self) -> Option<(KEY, DATA)>
+    {
+        let past = match (&self.hi, self.iter.peek()) {
+            (&Some(ref hi), Some(&(ref k, _))) => k.cmp(hi) != Less,
+            _ => false
+        };
+        if past {
+            return None;
+        }
+        self.iter.next()
+    }
+}
+
+impl<KEY: TotalOrd, DATA, I: Iterator<(KEY, DATA)>> Seekable<KEY> for LinearSeekMap<KEY, DATA, I>
+{
+    fn seek(&mut self, key: &KEY)
+    {
+        loop {
+            match self.iter.peek() {
+                Some(&(ref k, _)) if k.cmp(key) == Less => (),
+                _ => return
+            }
+            self.iter.next();
+        }
+    }
+}
+
+impl<KEY: TotalOrd+Clone, I: Iterator<KEY>> Limit<KEY> for LinearSeekSet<KEY, I>
+{
+    fn limit(&mut self, key: &KEY)
+    {
+        let tighter = match self.hi {
+            Some(ref hi) => key.cmp(hi) == Less,
+            None => true
+        };
+        if tighter {
+            self.hi = Some(key.clone());
+        }
+    }
+}
+
+impl<KEY: TotalOrd+Clone, DATA, I: Iterator<(KEY, DATA)>> Limit<KEY> for LinearSeekMap<KEY, DATA, I>
+{
+    fn limit(&mut self, key: &KEY)
+    {
+        let tighter = match self.hi {
+            Some(ref hi) => key.cmp(hi) == Less,
+            None => true
+        };
+        if tighter {
+            self.hi = Some(key.clone());
+        }
+    }
+}
+
+// Intersect any number of sorted streams at once, leapfrog style: every
+// stream is seeked to the largest key seen so far until all of them agree.
+// Plain iterators can take part through linear_join_sets_n/linear_join_maps_n.
+
+pub struct JoinSetsNIterator<I> {iters: Vec<I>, done: bool}
+
+pub fn join_sets_n<KEY: TotalOrd, I: Iterator<KEY>+Seekable<KEY>>(iters: Vec<I>) -> JoinSetsNIterator<I>
+{
+    let done = iters.len() == 0;
+    JoinSetsNIterator {iters: iters, done: done}
+}
+
+impl<KEY: TotalOrd, I: Iterator<KEY>+Seekable<KEY>> Iterator<KEY> for JoinSetsNIterator<I>
+{
+    #[inline(never)]
+    fn next(&mut self) -> Option<KEY>
+    {
+        if self.done {
+            return None;
+        }
+
+        let mut keys = Vec::with_capacity(self.iters.len());
+        for iter in self.iters.mut_iter() {
+            match iter.next() {
+                None => {
+                    self.done = true;
+                    return None;
+                },
+                Some(key) => keys.push(key)
+            }
+        }
+
+        let mut max = 0;
+        'search: loop {
+            for i in range(0, keys.len()) {
+                while keys.get(i).cmp(keys.get(max)) == Less {
+                    self.iters.get_mut(i).seek(keys.get(max));
+                    match self.iters.get_mut(i).next() {
+                        None => {
+                            self.done = true;
+                            return None;
+                        },
+                        Some(key) => { *keys.get_mut(i) = key; }
+                    }
+                }
+
+                if keys.get(i).cmp(keys.get(max)) == Greater {
+                    max = i;
+                    continue 'search;
+                }
+            }
+            return keys.move_iter().next();
+        }
+    }
+}
+
+pub struct JoinMapsNIterator<I> {iters: Vec<I>, done: bool}
+
+// like join_sets_n, yields each shared key with the data of every stream
+pub fn join_maps_n<KEY: TotalOrd, DATA, I: Iterator<(KEY, DATA)>+Seekable<KEY>>(iters: Vec<I>) -> JoinMapsNIterator<I>
+{
+    let done = iters.len() == 0;
+    JoinMapsNIterator {iters: iters, done: done}
+}
+
+impl<KEY: TotalOrd, DATA, I: Iterator<(KEY, DATA)>+Seekable<KEY>> Iterator<(KEY, Vec<DATA>)> for JoinMapsNIterator<I>
+{
+    #[inline(never)]
+    fn next(&mut self) -> Option<(KEY, Vec<DATA>)>
+    {
+        if self.done {
+            return None;
+        }
+
+        let mut keys = Vec::with_capacity(self.iters.len());
+        let mut data = Vec::with_capacity(self.iters.len());
+        for iter in self.iters.mut_iter() {
+            match iter.next() {
+                None => {
+                    self.done = true;
+                    return None;
+                },
+                Some((k, d)) => {
+                    keys.push(k);
+                    data.push(d);
+                }
+            }
+        }
+
+        let mut max = 0;
+        'search: loop {
+            for i in range(0, keys.len()) {
+                while keys.get(i).cmp(keys.get(max)) == Less {
+                    self.iters.get_mut(i).seek(keys.get(max));
+                    match self.iters.get_mut(i).next() {
+                        None => {
+                            self.done = true;
+                            return None;
+                        },
+                        Some((k, d)) => {
+                            *keys.get_mut(i) = k;
+                            *data.get_mut(i) = d;
+                        }
+                    }
+                }
+
+                if keys.get(i).cmp(keys.get(max)) == Greater {
+                    max = i;
+                    continue 'search;
+                }
+            }
+            return Some((keys.move_iter().next().unwrap(), data));
+        }
+    }
+}
+
+// join_sets_n and join_maps_n over plain sorted iterators, each one is
+// wrapped in linear_seek_set/linear_seek_map. A blanket Seekable impl for
+// every iterator would overlap the impls of the tree iterators.
+
+pub fn linear_join_sets_n<KEY: TotalOrd, I: Iterator<KEY>>(iters: Vec<I>) -> JoinSetsNIterator<LinearSeekSet<KEY, I>>
+{
+    join_sets_n(iters.move_iter().map(|iter| linear_seek_set(iter)).collect())
+}
+
+pub fn linear_join_maps_n<KEY: TotalOrd, DATA, I: Iterator<(KEY, DATA)>>(iters: Vec<I>) -> JoinMapsNIterator<LinearSeekMap<KEY, DATA, I>>
+{
+    join_maps_n(iters.move_iter().map(|iter| linear_seek_map(iter)).collect())
+}
+
+// Set algebra over sorted streams of unique keys. Intersection and
+// difference are the set join and set anti join.
+
+pub fn intersection<KEY: TotalOrd, IterA: Iterator<KEY>, IterB: Iterator<KEY>>
+    (a: IterA, b: IterB) -> JoinSetIterator<IterA, IterB>
+{
+    join_sets(a, b)
+}
+
+pub fn difference<KEY: TotalOrd, IterA: Iterator<KEY>, IterB: Iterator<KEY>>
+    (a: IterA, b: IterB) -> AntiJoinSetIterator<KEY, IterA, IterB>
+{
+    anti_join_sets(a, b)
+}
+
+pub struct UnionIterator<KEY, A, B> {a: Peekable<KEY, A>, b: Peekable<KEY, B>}
+
+pub fn union<KEY: TotalOrd, IterA: Iterator<KEY>, IterB: Iterator<KEY>>
+    (a: IterA, b: IterB) -> UnionIterator<KEY, IterA, IterB>
+{
+    UnionIterator {a: a.peekable(), b: b.peekable()}
+}
+
+impl<KEY: TotalOrd, IterA: Iterator<KEY>, IterB: Iterator<KEY>>
+    Iterator<KEY> for UnionIterator<KEY, IterA, IterB>
+{
+    #[inline(never)]
+    fn next(&mut self) -> Option<KEY>
+    {
+        let ord = match (self.a.peek(), self.b.peek()) {
+            (Some(key_a), Some(key_b)) => key_a.cmp(key_b),
+            (Some(_), None) => Less,
+            (None, Some(_)) => Greater,
+            (None, None) => return None
+        };
+
+        match ord {
+            Less => self.a.next(),
+            Equal => {
+                self.b.next();
+                self.a.next()
+            },
+            Greater => self.b.next()
+        }
+    }
+}
+
+pub struct SymmetricDifferenceIterator<KEY, A, B> {a: Peekable<KEY, A>, b: Peekable<KEY, B>}
+
+pub fn symmetric_difference<KEY: TotalOrd, IterA: Iterator<KEY>, IterB: Iterator<KEY>>
+    (a: IterA, b: IterB) -> SymmetricDifferenceIterator<KEY, IterA, IterB>
+{
+    SymmetricDifferenceIterator {a: a.peekable(), b: b.peekable()}
+}
+
+impl<KEY: TotalOrd, IterA: Iterator<KEY>, IterB: Iterator<KEY>>
+    Iterator<KEY> for SymmetricDifferenceIterator<KEY, IterA, IterB>
+{
+    #[inline(never)]
+    fn next(&mut self) -> Option<KEY>
+    {
+        loop {
+            let ord = match (self.a.peek(), self.b.peek()) {
+                (Some(key_a), Some(key_b)) => key_a.cmp(key_b),
+                (Some(_), None) => Less,
+                (None, Some(_)) => Greater,
+                (None, None) => return None
+            };
+
+            match ord {
+                Less => return self.a.next(),
+                Equal => {
+                    self.a.next();
+                    self.b.next();
+                },
+                Greater => return self.b.next()
+            }
+        }
+    }
+}
+
+// Seek joins restricted to keys in [lo, hi), a missing bound leaves that
+// side open. Every input starts at lo and ends before hi on its own, for
+// trees both are found by a descent, other streams can take part through
+// linear_seek_set/linear_seek_map.
+
+fn bound<KEY, I: Seekable<KEY>+Limit<KEY>>(iter: &mut I, lo: &Option<KEY>, hi: &Option<KEY>)
+{
+    match *lo {
+        Some(ref lo) => iter.seek(lo),
+        None => ()
+    }
+    match *hi {
+        Some(ref hi) => iter.limit(hi),
+        None => ()
+    }
+}
+
+pub fn join_maps_range<KEY: TotalOrd, DATA_A, DATA_B,
+                       IterA: Iterator<(KEY, DATA_A)>+Seekable<KEY>+Limit<KEY>,
+                       IterB: Iterator<(KEY, DATA_B)>+Seekable<KEY>+Limit<KEY>>
+    (a: IterA, b: IterB, lo: Option<KEY>, hi: Option<KEY>) -> SeekJoinMapIterator<IterA, IterB>
+{
+    let (mut a, mut b) = (a, b);
+    bound(&mut a, &lo, &hi);
+    bound(&mut b, &lo, &hi);
+    seek_join_maps(a, b)
+}
+
+pub fn join_sets_range<KEY: TotalOrd,
+                       IterA: Iterator<KEY>+Seekable<KEY>+Limit<KEY>,
+                       IterB: Iterator<KEY>+Seekable<KEY>+Limit<KEY>>
+    (a: IterA, b: IterB, lo: Option<KEY>, hi: Option<KEY>) -> SeekJoinSetIterator<IterA, IterB>
+{
+    let (mut a, mut b) = (a, b);
+    bound(&mut a, &lo, &hi);
+    bound(&mut b, &lo, &hi);
+    seek_join_sets(a, b)
+}
+
+pub fn join_set_to_map_range<KEY: TotalOrd, DATA,
+                             SetIter: Iterator<KEY>+Seekable<KEY>+Limit<KEY>,
+                             MapIter: Iterator<(KEY, DATA)>+Seekable<KEY>+Limit<KEY>>
+    (set: SetIter, map: MapIter, lo: Option<KEY>, hi: Option<KEY>) -> SeekJoinMapSetIterator<SetIter, MapIter>
+{
+    let (mut set, mut map) = (set, map);
+    bound(&mut set, &lo, &hi);
+    bound(&mut map, &lo, &hi);
+    seek_join_set_to_map(set, map)
+}
+
+pub enum JoinError {
+    // the item at position (counted from zero) of one of the inputs
+    // has a smaller key than the item before it
+    Unsorted { position: uint }
+}
+
+// The multi joins allow repeated keys on either side and yield the
+// cross product of each pair of equal-key runs, so keys and data have
+// to be Clone. In strict mode an input going backwards is reported and
+// ends the join instead of silently skipping matches.
+struct MultiJoin<KEY, DATA_A, DATA_B, A, B> {
+    a: Peekable<(KEY, DATA_A), A>,
+    b: Peekable<(KEY, DATA_B), B>,
+    strict: bool,
+    failed: bool,
+    last_a: Option<KEY>,
+    last_b: Option<KEY>,
+    pos_a: uint,
+    pos_b: uint,
+    key: Option<KEY>,
+    run_a: Vec<DATA_A>,
+    run_b: Vec<DATA_B>,
+    i: uint,
+    j: uint
+}
+
+fn check_order<KEY: TotalOrd+Clone>(last: &mut Option<KEY>, pos: &mut uint, key: &KEY) -> Result<(), JoinError>
+{
+    match *last {
+        Some(ref l) if key.cmp(l) == Less => return Err(Unsorted {position: *pos}),
+        _ => ()
+    }
+    *last = Some(key.clone());
+    *pos += 1;
+    Ok(())
+}
+
+impl<KEY: TotalOrd+Clone, DATA_A: Clone, DATA_B: Clone, IterA: Iterator<(KEY, DATA_A)>, IterB: Iterator<(KEY, DATA_B)>>
+    MultiJoin<KEY, DATA_A, DATA_B, IterA, IterB>
+{
+    fn new(a: IterA, b: IterB, strict: bool) -> MultiJoin<KEY, DATA_A, DATA_B, IterA, IterB>
+    {
+        MultiJoin {
+            a: a.peekable(),
+            b: b.peekable(),
+            strict: strict,
+            failed: false,
+            last_a: None,
+            last_b: None,
+            pos_a: 0,
+            pos_b: 0,
+            key: None,
+            run_a: Vec::new(),
+            run_b: Vec::new(),
+            i: 0,
+            j: 0
+        }
+    }
+
+    fn take_a(&mut self) -> Result<DATA_A, JoinError>
+    {
+        let (key, data) = self.a.next().unwrap();
+        if self.strict {
+            try!(check_order(&mut self.last_a, &mut self.pos_a, &key));
+        }
+        Ok(data)
+    }
+
+    fn take_b(&mut self) -> Result<DATA_B, JoinError>
+    {
+        let (key, data) = self.b.next().unwrap();
+        if self.strict {
+            try!(check_order(&mut self.last_b, &mut self.pos_b, &key));
+        }
+        Ok(data)
+    }
+
+    fn same_a(&mut self, key: &KEY) -> bool
+    {
+        match self.a.peek() {
+            Some(&(ref k, _)) => k.cmp(key) == Equal,
+            None => false
+        }
+    }
+
+    fn same_b(&mut self, key: &KEY) -> bool
+    {
+        match self.b.peek() {
+            Some(&(ref k, _)) => k.cmp(key) == Equal,
+            None => false
+        }
+    }
+
+    // find the next pair of runs with equal keys
+    fn next_runs(&mut self) -> Result<bool, JoinError>
+    {
+        self.run_a.clear();
+        self.run_b.clear();
+        self.i = 0;
+        self.j = 0;
+
+        loop {
+            let ord = match (self.a.peek(), self.b.peek()) {
+                (Some(&(ref key_a, _)), Some(&(ref key_b, _))) => key_a.cmp(key_b),
+                (_, _) => return Ok(false)
+            };
+
+            match ord {
+                Less => { try!(self.take_a()); },
+                Greater => { try!(self.take_b()); },
+                Equal => {
+                    let key = match self.a.peek() {
+                        Some(&(ref key, _)) => key.clone(),
+                        None => fail!("peeked above")
+                    };
+
+                    while self.same_a(&key) {
+                        let data = try!(self.take_a());
+                        self.run_a.push(data);
+                    }
+                    while self.same_b(&key) {
+                        let data = try!(self.take_b());
+                        self.run_b.push(data);
+                    }
+
+                    self.key = Some(key);
+                    return Ok(true);
+                }
+            }
+        }
+    }
+
+    fn step(&mut self) -> Option<Result<(KEY, (DATA_A, DATA_B)), JoinError>>
+    {
+        if self.failed {
+            return None;
+        }
+
+        if self.i == self.run_a.len() {
+            match self.next_runs() {
+                Ok(true) => (),
+                Ok(false) => return None,
+                Err(e) => {
+                    self.failed = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+
+        let item = (self.key.get_ref().clone(),
+                    (self.run_a.get(self.i).clone(), self.run_b.get(self.j).clone()));
+
+        self.j += 1;
+        if self.j == self.run_b.len() {
+            self.j = 0;
+            self.i += 1;
+        }
+
+        Some(Ok(item))
+    }
+}
+
+pub struct MultiJoinMapIterator<KEY, DATA_A, DATA_B, A, B> {
+    join: MultiJoin<KEY, DATA_A, DATA_B, A, B>
+}
+
+pub fn join_maps_multi<KEY: TotalOrd+Clone, DATA_A: Clone, DATA_B: Clone, IterA: Iterator<(KEY, DATA_A)>, IterB: Iterator<(KEY, DATA_B)>>
+    (a: IterA, b: IterB) -> MultiJoinMapIterator<KEY, DATA_A, DATA_B, IterA, IterB>
+{
+    MultiJoinMapIterator {join: MultiJoin::new(a, b, false)}
+}
+
+impl<KEY: TotalOrd+Clone, DATA_A: Clone, DATA_B: Clone, IterA: Iterator<(KEY, DATA_A)>, IterB: Iterator<(KEY, DATA_B)>>
+    Iterator<(KEY, (DATA_A, DATA_B))> for MultiJoinMapIterator<KEY, DATA_A, DATA_B, IterA, IterB>
+{
+    #[inline(never)]
+    fn next(&mut self) -> Option<(KEY, (DATA_A, DATA_B))>
+    {
+        match self.join.step() {
+            Some(Ok(item)) => Some(item),
+            Some(Err(_)) => fail!("only strict joins check the order"),
+            None => None
+        }
+    }
+}
+
+pub struct StrictMultiJoinMapIterator<KEY, DATA_A, DATA_B, A, B> {
+    join: MultiJoin<KEY, DATA_A, DATA_B, A, B>
+}
+
+pub fn strict_join_maps_multi<KEY: TotalOrd+Clone, DATA_A: Clone, DATA_B: Clone, IterA: Iterator<(KEY, DATA_A)>, IterB: Iterator<(KEY, DATA_B)>>
+    (a: IterA, b: IterB) -> StrictMultiJoinMapIterator<KEY, DATA_A, DATA_B, IterA, IterB>
+{
+    StrictMultiJoinMapIterator {join: MultiJoin::new(a, b, true)}
+}
+
+impl<KEY: TotalOrd+Clone, DATA_A: Clone, DATA_B: Clone, IterA: Iterator<(KEY, DATA_A)>, IterB: Iterator<(KEY, DATA_B)>>
+    Iterator<Result<(KEY, (DATA_A, DATA_B)), JoinError>> for StrictMultiJoinMapIterator<KEY, DATA_A, DATA_B, IterA, IterB>
+{
+    #[inline(never)]
+    fn next(&mut self) -> Option<Result<(KEY, (DATA_A, DATA_B)), JoinError>>
+    {
+        self.join.step()
+    }
+}
+
+// Multiset versions of the multi joins for streams of bare keys, a key
+// repeated m times in a and n times in b is yielded m * n times.
+
+struct WithUnit<I> {iter: I}
+
+impl<KEY, I: Iterator<KEY>> Iterator<(KEY, ())> for WithUnit<I>
+{
+    fn next(&mut self) -> Option<(KEY, ())>
+    {
+        self.iter.next().map(|key| (key, ()))
+    }
+}
+
+pub struct MultiJoinSetIterator<KEY, A, B> {
+    join: MultiJoin<KEY, (), (), WithUnit<A>, WithUnit<B>>
+}
+
+pub fn join_sets_multi<KEY: TotalOrd+Clone, IterA: Iterator<KEY>, IterB: Iterator<KEY>>
+    (a: IterA, b: IterB) -> MultiJoinSetIterator<KEY, IterA, IterB>
+{
+    MultiJoinSetIterator {join: MultiJoin::new(WithUnit {iter: a}, WithUnit {iter: b}, false)}
+}
+
+impl<KEY: TotalOrd+Clone, IterA: Iterator<KEY>, IterB: Iterator<KEY>>
+    Iterator<KEY> for MultiJoinSetIterator<KEY, IterA, IterB>
+{
+    #[inline(never)]
+    fn next(&mut self) -> Option<KEY>
+    {
+        match self.join.step() {
+            Some(Ok((key, _))) => Some(key),
+            Some(Err(_)) => fail!("only strict joins check the order"),
+            None => None
+        }
+    }
+}
+
+pub struct StrictMultiJoinSetIterator<KEY, A, B> {
+    join: MultiJoin<KEY, (), (), WithUnit<A>, WithUnit<B>>
+}
+
+pub fn strict_join_sets_multi<KEY: TotalOrd+Clone, IterA: Iterator<KEY>, IterB: Iterator<KEY>>
+    (a: IterA, b: IterB) -> StrictMultiJoinSetIterator<KEY, IterA, IterB>
+{
+    StrictMultiJoinSetIterator {join: MultiJoin::new(WithUnit {iter: a}, WithUnit {iter: b}, true)}
+}
+
+impl<KEY: TotalOrd+Clone, IterA: Iterator<KEY>, IterB: Iterator<KEY>>
+    Iterator<Result<KEY, JoinError>> for StrictMultiJoinSetIterator<KEY, IterA, IterB>
+{
+    #[inline(never)]
+    fn next(&mut self) -> Option<Result<KEY, JoinError>>
+    {
+        self.join.step().map(|item| item.map(|(key, _)| key))
+    }
+}
+
+// Adaptors that report the first key smaller than the one before it
+// instead of passing it on, after that the stream ends.
+
+pub struct CheckedSorted<KEY, I> {iter: I, last: Option<KEY>, pos: uint, failed: bool}
+
+pub fn checked_sorted<KEY: TotalOrd+Clone, I: Iterator<KEY>>(iter: I) -> CheckedSorted<KEY, I>
+{
+    CheckedSorted {iter: iter, last: None, pos: 0, failed: false}
+}
+
+impl<KEY: TotalOrd+Clone, I: Iterator<KEY>> Iterator<Result<KEY, JoinError>> for CheckedSorted<KEY, I>
+{
+    fn next(&mut self) -> Option<Result<KEY, JoinError>>
+    {
+        if self.failed {
+            return None;
+        }
+
+        match self.iter.next() {
+            None => None,
+            Some(key) => {
+                match check_order(&mut self.last, &mut self.pos, &key) {
+                    Ok(()) => Some(Ok(key)),
+                    Err(e) => {
+                        self.failed = true;
+                        Some(Err(e))
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub struct CheckedSortedMap<KEY, I> {iter: I, last: Option<KEY>, pos: uint, failed: bool}
+
+// checked_sorted for (KEY, DATA) streams, only the keys are compared
+pub fn checked_sorted_map<KEY: TotalOrd+Clone, DATA, I: Iterator<(KEY, DATA)>>(iter: I) -> CheckedSortedMap<KEY, I>
+{
+    CheckedSortedMap {iter: iter, last: None, pos: 0, failed: false}
+}
+
+impl<KEY: TotalOrd+Clone, DATA, I: Iterator<(KEY, DATA)>> Iterator<Result<(KEY, DATA), JoinError>> for CheckedSortedMap<KEY, I>
+{
+    fn next(&mut self) -> Option<Result<(KEY, DATA), JoinError>>
+    {
+        if self.failed {
+            return None;
+        }
+
+        match self.iter.next() {
+            None => None,
+            Some((key, data)) => {
+                match check_order(&mut self.last, &mut self.pos, &key) {
+                    Ok(()) => Some(Ok((key, data))),
+                    Err(e) => {
+                        self.failed = true;
+                        Some(Err(e))
+                    }
+                }
+            }
+        }
+    }
+}
+
+// ends a checked stream at its first error and keeps the error around
+// so a checked join can report it once the inner join stops
+struct StopOnError<I> {iter: I, error: Option<JoinError>, done: bool}
+
+fn stop_on_error<I>(iter: I) -> StopOnError<I>
+{
+    StopOnError {iter: iter, error: None, done: false}
+}
+
+impl<A, I: Iterator<Result<A, JoinError>>> Iterator<A> for StopOnError<I>
+{
+    fn next(&mut self) -> Option<A>
+    {
+        if self.done {
+            return None;
+        }
+
+        match self.iter.next() {
+            Some(Ok(item)) => Some(item),
+            Some(Err(e)) => {
+                self.error = Some(e);
+                self.done = true;
+                None
+            },
+            None => {
+                self.done = true;
+                None
+            }
+        }
+    }
+}
+
+fn take_error<A, B>(a: &mut StopOnError<A>, b: &mut StopOnError<B>) -> Option<JoinError>
+{
+    match (a.error.take(), b.error.take()) {
+        (Some(e), _) => Some(e),
+        (None, e) => e
+    }
+}
+
+// The checked joins yield Err(Unsorted) as their last item if either
+// input turned out not to be sorted, instead of silently missing rows.
+// The inner join stops as soon as either side runs out, what is left of
+// the other side is still read to the end to check its order as well.
+
+pub struct CheckedJoinMapIterator<KEY, A, B> {
+    join: JoinMapIterator<StopOnError<CheckedSortedMap<KEY, A>>, StopOnError<CheckedSortedMap<KEY, B>>>,
+    done: bool
+}
+
+pub fn checked_join_maps<KEY: TotalOrd+Clone, DATA_A, DATA_B, IterA: Iterator<(KEY, DATA_A)>, IterB: Iterator<(KEY, DATA_B)>>
+    (a: IterA, b: IterB) -> CheckedJoinMapIterator<KEY, IterA, IterB>
+{
+    CheckedJoinMapIterator {
+        join: join_maps(stop_on_error(checked_sorted_map(a)), stop_on_error(checked_sorted_map(b))),
+        done: false
+    }
+}
+
+impl<KEY: TotalOrd+Clone, DATA_A, DATA_B, IterA: Iterator<(KEY, DATA_A)>, IterB: Iterator<(KEY, DATA_B)>>
+    Iterator<Result<(KEY, (DATA_A, DATA_B)), JoinError>> for CheckedJoinMapIterator<KEY, IterA, IterB>
+{
+    fn next(&mut self) -> Option<Result<(KEY, (DATA_A, DATA_B)), JoinError>>
+    {
+        if self.done {
+            return None;
+        }
+
+        match self.join.next() {
+            Some(item) => Some(Ok(item)),
+            None => {
+                self.done = true;
+                while self.join.a.next().is_some() {}
+                while self.join.b.next().is_some() {}
+                take_error(&mut self.join.a, &mut self.join.b).map(|e| Err(e))
+            }
+        }
+    }
+}
+
+pub struct CheckedJoinSetIterator<KEY, A, B> {
+    join: JoinSetIterator<StopOnError<CheckedSorted<KEY, A>>, StopOnError<CheckedSorted<KEY, B>>>,
+    done: bool
+}
+
+pub fn checked_join_sets<KEY: TotalOrd+Clone, IterA: Iterator<KEY>, IterB: Iterator<KEY>>
+    (a: IterA, b: IterB) -> CheckedJoinSetIterator<KEY, IterA, IterB>
+{
+    CheckedJoinSetIterator {
+        join: join_sets(stop_on_error(checked_sorted(a)), stop_on_error(checked_sorted(b))),
+        done: false
+    }
+}
+
+impl<KEY: TotalOrd+Clone, IterA: Iterator<KEY>, IterB: Iterator<KEY>>
+    Iterator<Result<KEY, JoinError>> for CheckedJoinSetIterator<KEY, IterA, IterB>
+{
+    fn next(&mut self) -> Option<Result<KEY, JoinError>>
+    {
+        if self.done {
+            return None;
+        }
+
+        match self.join.next() {
+            Some(key) => Some(Ok(key)),
+            None => {
+                self.done = true;
+                while self.join.a.next().is_some() {}
+                while self.join.b.next().is_some() {}
+                take_error(&mut self.join.a, &mut self.join.b).map(|e| Err(e))
+            }
+        }
+    }
+}
+
+pub struct CheckedJoinMapSetIterator<KEY, A, B> {
+    join: JoinMapSetIterator<StopOnError<CheckedSorted<KEY, A>>, StopOnError<CheckedSortedMap<KEY, B>>>,
+    done: bool
+}
+
+pub fn checked_join_set_to_map<KEY: TotalOrd+Clone, DATA, SetIter: Iterator<KEY>, MapIter: Iterator<(KEY, DATA)>>
+    (set: SetIter, map: MapIter) -> CheckedJoinMapSetIterator<KEY, SetIter, MapIter>
+{
+    CheckedJoinMapSetIterator {
+        join: join_set_to_map(stop_on_error(checked_sorted(set)), stop_on_error(checked_sorted_map(map))),
+        done: false
+    }
+}
+
+impl<KEY: TotalOrd+Clone, DATA, SetIter: Iterator<KEY>, MapIter: Iterator<(KEY, DATA)>>
+    Iterator<Result<(KEY, DATA), JoinError>> for CheckedJoinMapSetIterator<KEY, SetIter, MapIter>
+{
+    fn next(&mut self) -> Option<Result<(KEY, DATA), JoinError>>
+    {
+        if self.done {
+            return None;
+        }
+
+        match self.join.next() {
+            Some(item) => Some(Ok(item)),
+            None => {
+                self.done = true;
+                while self.join.set.next().is_some() {}
+                while self.join.map.next().is_some() {}
+                take_error(&mut self.join.set, &mut self.join.map).map(|e| Err(e))
+            }
+        }
+    }
+}
+
+// Joins two streams of records sorted by a key extracted from each
+// record, the keys are compared but only the records are yielded. A key
+// can borrow from the record when the streams yield references.
+pub struct JoinByIterator<'a, RA, RB, KEY, A, B> {
+    a: A,
+    b: B,
+    key_a: 'a |&RA| -> KEY,
+    key_b: 'a |&RB| -> KEY
+}
+
+pub fn join_by<'a, KEY: TotalOrd, RA, RB, IterA: Iterator<RA>, IterB: Iterator<RB>>
+    (a: IterA, b: IterB, key_a: 'a |&RA| -> KEY, key_b: 'a |&RB| -> KEY) -> JoinByIterator<'a, RA, RB, KEY, IterA, IterB>
+{
+    JoinByIterator {a: a, b: b, key_a: key_a, key_b: key_b}
+}
+
+impl<'a, KEY: TotalOrd, RA, RB, IterA: Iterator<RA>, IterB: Iterator<RB>>
+    Iterator<(RA, RB)> for JoinByIterator<'a, RA, RB, KEY, IterA, IterB>
+{
+    #[inline(never)]
+    fn next(&mut self) -> Option<(RA, RB)>
+    {
+        let mut rec_a = match self.a.next() {
+            None => return None,
+            Some(rec) => rec
+        };
+
+        let mut rec_b = match self.b.next() {
+            None => return None,
+            Some(rec) => rec
+        };
+
+        let mut key_a = (self.key_a)(&rec_a);
+        let mut key_b = (self.key_b)(&rec_b);
+
+        loop {
+            match key_a.cmp(&key_b) {
+                Less => {
+                    match self.a.next() {
+                        None => return None,
+                        Some(rec) => {
+                            key_a = (self.key_a)(&rec);
+                            rec_a = rec;
+                        }
+                    };
+                },
+                Equal => return Some((rec_a, rec_b)),
+                Greater => {
+                    match self.b.next() {
+                        None => return None,
+                        Some(rec) => {
+                            key_b = (self.key_b)(&rec);
+                            rec_b = rec;
+                        }
+                    };
+                }
+            }
+        }
+    }
+}
+
+// Group runs of equal keys in a sorted (KEY, DATA) stream, yielding one
+// item per key. Only one group is held at a time so these compose with
+// the joins, e.g. count_by_key(join_maps(a, b)). To group on part of a
+// key map the stream to that part first.
+
+pub struct GroupByKeyIterator<KEY, DATA, I> {iter: Peekable<(KEY, DATA), I>}
+
+pub fn group_by_key<KEY: TotalOrd, DATA, I: Iterator<(KEY, DATA)>>(iter: I) -> GroupByKeyIterator<KEY, DATA, I>
+{
+    GroupByKeyIterator {iter: iter.peekable()}
+}
+
+impl<KEY: TotalOrd, DATA, I: Iterator<(KEY, DATA)>> GroupByKeyIterator<KEY, DATA, I>
+{
+    fn fold_group<ACC>(&mut self, init: ACC, f: |ACC, DATA| -> ACC) -> Option<(KEY, ACC)>
+    {
+        let (key, data) = match self.iter.next() {
+            None => return None,
+            Some(entry) => entry
+        };
+
+        let mut acc = f(init, data);
+        loop {
+            let same = match self.iter.peek() {
+                Some(&(ref k, _)) => k.cmp(&key) == Equal,
+                None => false
+            };
+            if !same {
+                return Some((key, acc));
+            }
+            let (_, data) = self.iter.next().unwrap();
+            acc = f(acc, data);
+        }
+    }
+
+    // groups are never empty, so there is always a first item to start from
+    fn reduce_group(&mut self, f: |DATA, DATA| -> DATA) -> Option<(KEY, DATA)>
+    {
+        match self.fold_group(None, |acc, data| {
+            match acc {
+                None => Some(data),
+                Some(acc) => Some(f(acc, data))
+            }
+        }) {
+            Some((key, acc)) => Some((key, acc.unwrap())),
+            None => None
+        }
+    }
+}
+
+impl<KEY: TotalOrd, DATA, I: Iterator<(KEY, DATA)>> Iterator<(KEY, Vec<DATA>)> for GroupByKeyIterator<KEY, DATA, I>
+{
+    fn next(&mut self) -> Option<(KEY, Vec<DATA>)>
+    {
+        self.fold_group(Vec::new(), |mut group, data| {
+            group.push(data);
+            group
+        })
+    }
+}
+
+pub struct CountByKeyIterator<KEY, DATA, I> {groups: GroupByKeyIterator<KEY, DATA, I>}
+
+pub fn count_by_key<KEY: TotalOrd, DATA, I: Iterator<(KEY, DATA)>>(iter: I) -> CountByKeyIterator<KEY, DATA, I>
+{
+    CountByKeyIterator {groups: group_by_key(iter)}
+}
+
+impl<KEY: TotalOrd, DATA, I: Iterator<(KEY, DATA)>> Iterator<(KEY, uint)> for CountByKeyIterator<KEY, DATA, I>
+{
+    fn next(&mut self) -> Option<(KEY, uint)>
+    {
+        self.groups.fold_group(0u, |count, _| count + 1)
+    }
+}
+
+pub struct SumByKeyIterator<KEY, DATA, I> {groups: GroupByKeyIterator<KEY, DATA, I>}
+
+pub fn sum_by_key<KEY: TotalOrd, DATA: Add<DATA, DATA>, I: Iterator<(KEY, DATA)>>(iter: I) -> SumByKeyIterator<KEY, DATA, I>
+{
+    SumByKeyIterator {groups: group_by_key(iter)}
+}
+
+impl<KEY: TotalOrd, DATA: Add<DATA, DATA>, I: Iterator<(KEY, DATA)>> Iterator<(KEY, DATA)> for SumByKeyIterator<KEY, DATA, I>
+{
+    fn next(&mut self) -> Option<(KEY, DATA)>
+    {
+        self.groups.reduce_group(|a, b| a + b)
+    }
+}
+
+pub struct MinByKeyIterator<KEY, DATA, I> {groups: GroupByKeyIterator<KEY, DATA, I>}
+
+pub fn min_by_key<KEY: TotalOrd, DATA: TotalOrd, I: Iterator<(KEY, DATA)>>(iter: I) -> MinByKeyIterator<KEY, DATA, I>
+{
+    MinByKeyIterator {groups: group_by_key(iter)}
+}
+
+impl<KEY: TotalOrd, DATA: TotalOrd, I: Iterator<(KEY, DATA)>> Iterator<(KEY, DATA)> for MinByKeyIterator<KEY, DATA, I>
+{
+    fn next(&mut self) -> Option<(KEY, DATA)>
+    {
+        self.groups.reduce_group(|a, b| if b.cmp(&a) == Less { b } else { a })
+    }
+}
+
+pub struct MaxByKeyIterator<KEY, DATA, I> {groups: GroupByKeyIterator<KEY, DATA, I>}
+
+pub fn max_by_key<KEY: TotalOrd, DATA: TotalOrd, I: Iterator<(KEY, DATA)>>(iter: I) -> MaxByKeyIterator<KEY, DATA, I>
+{
+    MaxByKeyIterator {groups: group_by_key(iter)}
+}
+
+impl<KEY: TotalOrd, DATA: TotalOrd, I: Iterator<(KEY, DATA)>> Iterator<(KEY, DATA)> for MaxByKeyIterator<KEY, DATA, I>
+{
+    fn next(&mut self) -> Option<(KEY, DATA)>
+    {
+        self.groups.reduce_group(|a, b| if b.cmp(&a) == Greater { b } else { a })
+    }
+}
+
+// every group starts from a clone of init
+pub struct FoldByKeyIterator<'a, KEY, DATA, ACC, I> {
+    groups: GroupByKeyIterator<KEY, DATA, I>,
+    init: ACC,
+    fold: 'a |ACC, DATA| -> ACC
+}
+
+pub fn fold_by_key<'a, KEY: TotalOrd, DATA, ACC: Clone, I: Iterator<(KEY, DATA)>>
+    (iter: I, init: ACC, fold: 'a |ACC, DATA| -> ACC) -> FoldByKeyIterator<'a, KEY, DATA, ACC, I>
+{
+    FoldByKeyIterator {groups: group_by_key(iter), init: init, fold: fold}
+}
+
+impl<'a, KEY: TotalOrd, DATA, ACC: Clone, I: Iterator<(KEY, DATA)>>
+    Iterator<(KEY, ACC)> for FoldByKeyIterator<'a, KEY, DATA, ACC, I>
+{
+    fn next(&mut self) -> Option<(KEY, ACC)>
+    {
+        let fold = &mut self.fold;
+        self.groups.fold_group(self.init.clone(), |acc, data| (*fold)(acc, data))
+    }
+}
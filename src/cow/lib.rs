@@ -4,8 +4,24 @@
 #![crate_type = "lib"]
 #![crate_type = "dylib"]
 #![allow(experimental)]
+#![feature(macro_rules, struct_variant)]
 
 extern crate sync;
+extern crate serialize;
 
 pub mod btree;
-pub mod join;
\ No newline at end of file
+pub mod join;
+pub mod reconcile;
+pub mod serial;
+pub mod snapshot;
+pub mod crc;
+pub mod wal;
+pub mod par;
+pub mod table;
+pub mod datalog;
+pub mod view;
+pub mod observed;
+pub mod multimap;
+pub mod ranges;
+pub mod vec;
+pub mod pqueue;
\ No newline at end of file
@@ -0,0 +1,110 @@
+use std::default::Default;
+
+use btree::{BTreeMap, BTreeMapIterator};
+
+// A map holding any number of values per key, stored as one entry per
+// (key, value) pair ordered by key and then value. A key with many
+// values is spread over the tree like any other run of entries, so
+// changing it only copies the nodes on the path to the change.
+pub struct BTreeMultiMap<K, V> {
+    map: BTreeMap<(K, V), ()>
+}
+
+pub struct BTreeMultiMapIterator<'a, K, V> {
+    iter: BTreeMapIterator<'a, (K, V), ()>
+}
+
+impl<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+TotalOrd+Send+Share> BTreeMultiMap<K, V>
+{
+    pub fn new() -> BTreeMultiMap<K, V>
+    {
+        BTreeMultiMap {
+            map: BTreeMap::new()
+        }
+    }
+
+    // number of (key, value) pairs
+    pub fn len(&self) -> uint
+    {
+        self.map.len()
+    }
+
+    // true if the pair was already present
+    pub fn insert(&mut self, key: K, value: V) -> bool
+    {
+        self.map.insert((key, value), ())
+    }
+
+    pub fn contains(&self, key: &K, value: &V) -> bool
+    {
+        self.map.find(&(key.clone(), value.clone())).is_some()
+    }
+
+    pub fn remove_one(&mut self, key: &K, value: &V) -> bool
+    {
+        self.map.remove(&(key.clone(), value.clone()))
+    }
+
+    // returns the number of values removed
+    pub fn remove_all(&mut self, key: &K) -> uint
+    {
+        let values: Vec<V> = self.get_all(key).map(|v| v.clone()).collect();
+        let count = values.len();
+        for value in values.move_iter() {
+            self.map.remove(&(key.clone(), value));
+        }
+        count
+    }
+
+    // the values of key in order
+    pub fn get_all<'a>(&'a self, key: &K) -> BTreeMultiMapIterator<'a, K, V>
+    {
+        let mut iter = self.map.iter();
+        iter.seek_by(|&(ref k, _)| k.cmp(key) == Less);
+        iter.limit_by(|&(ref k, _)| k.cmp(key) != Greater);
+        BTreeMultiMapIterator {iter: iter}
+    }
+
+    // the number of values of key, found without visiting them
+    pub fn count(&self, key: &K) -> uint
+    {
+        let end = self.map.rank_by(|&(ref k, _)| k.cmp(key) != Greater);
+        let start = self.map.rank_by(|&(ref k, _)| k.cmp(key) == Less);
+        end - start
+    }
+
+    // every pair in (key, value) order
+    pub fn iter<'a>(&'a self) -> BTreeMapIterator<'a, (K, V), ()>
+    {
+        self.map.iter()
+    }
+}
+
+impl<'a, K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+TotalOrd+Send+Share> Iterator<&'a V> for BTreeMultiMapIterator<'a, K, V>
+{
+    fn next(&mut self) -> Option<&'a V>
+    {
+        match self.iter.next() {
+            Some((&(_, ref v), _)) => Some(v),
+            None => None
+        }
+    }
+}
+
+impl<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+TotalOrd+Send+Share> Clone for BTreeMultiMap<K, V>
+{
+    fn clone(&self) -> BTreeMultiMap<K, V>
+    {
+        BTreeMultiMap {
+            map: self.map.clone()
+        }
+    }
+}
+
+impl<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+TotalOrd+Send+Share> Default for BTreeMultiMap<K, V>
+{
+    fn default() -> BTreeMultiMap<K, V>
+    {
+        BTreeMultiMap::new()
+    }
+}
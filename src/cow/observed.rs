@@ -0,0 +1,174 @@
+use std::default::Default;
+
+use btree::BTreeMap;
+
+// A BTreeMap that tells its subscribers about every change made through
+// it. Events are delivered in batches, one per operation, or one per
+// begin_batch/commit_batch pair. The plain BTreeMap is not involved in
+// any of this.
+
+#[deriving(Clone)]
+pub enum Event<K, V> {
+    Insert(K, V),
+    // key, new value, the value it replaced
+    Swap(K, V, Option<V>),
+    Pop(K, V),
+    Clear
+}
+
+pub trait Observer<K, V> {
+    fn notify(&mut self, batch: &[Event<K, V>]);
+}
+
+// forwards every batch, a subscriber that went away is ignored
+impl<K: Clone+Send, V: Clone+Send> Observer<K, V> for Sender<Vec<Event<K, V>>>
+{
+    fn notify(&mut self, batch: &[Event<K, V>])
+    {
+        let _ = self.try_send(Vec::from_slice(batch));
+    }
+}
+
+struct Subscriber<K, V> {
+    id: uint,
+    observer: ~Observer<K, V>
+}
+
+pub struct ObservedBTreeMap<K, V> {
+    map: BTreeMap<K, V>,
+    observers: Vec<Subscriber<K, V>>,
+    next_id: uint,
+    batch: Option<Vec<Event<K, V>>>
+}
+
+impl<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share> ObservedBTreeMap<K, V>
+{
+    pub fn new(map: BTreeMap<K, V>) -> ObservedBTreeMap<K, V>
+    {
+        ObservedBTreeMap {
+            map: map,
+            observers: Vec::new(),
+            next_id: 0,
+            batch: None
+        }
+    }
+
+    pub fn map<'a>(&'a self) -> &'a BTreeMap<K, V>
+    {
+        &self.map
+    }
+
+    pub fn unwrap(self) -> BTreeMap<K, V>
+    {
+        self.map
+    }
+
+    // returns an id that can be passed to unsubscribe
+    pub fn subscribe(&mut self, observer: ~Observer<K, V>) -> uint
+    {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.observers.push(Subscriber {id: id, observer: observer});
+        id
+    }
+
+    pub fn subscribe_channel(&mut self) -> Receiver<Vec<Event<K, V>>>
+    {
+        let (tx, rx) = channel();
+        self.subscribe(~tx as ~Observer<K, V>);
+        rx
+    }
+
+    pub fn unsubscribe(&mut self, id: uint) -> bool
+    {
+        match self.observers.iter().position(|s| s.id == id) {
+            Some(idx) => {
+                self.observers.remove(idx);
+                true
+            },
+            None => false
+        }
+    }
+
+    // events until commit_batch are delivered together
+    pub fn begin_batch(&mut self)
+    {
+        if self.batch.is_none() {
+            self.batch = Some(Vec::new());
+        }
+    }
+
+    pub fn commit_batch(&mut self)
+    {
+        match self.batch.take() {
+            Some(events) => if events.len() != 0 {
+                self.notify(events.as_slice());
+            },
+            None => ()
+        }
+    }
+
+    fn notify(&mut self, events: &[Event<K, V>])
+    {
+        for s in self.observers.mut_iter() {
+            s.observer.notify(events);
+        }
+    }
+
+    fn emit(&mut self, event: Event<K, V>)
+    {
+        match self.batch {
+            Some(ref mut batch) => {
+                batch.push(event);
+                return;
+            },
+            None => ()
+        }
+        self.notify(&[event]);
+    }
+
+    // events are only built when someone is listening
+    fn observed(&self) -> bool
+    {
+        self.observers.len() != 0
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> bool
+    {
+        if self.observed() {
+            self.emit(Insert(key.clone(), value.clone()));
+        }
+        self.map.insert(key, value)
+    }
+
+    pub fn swap(&mut self, key: K, value: V) -> Option<V>
+    {
+        if !self.observed() {
+            return self.map.swap(key, value);
+        }
+
+        let event_key = key.clone();
+        let event_value = value.clone();
+        let old = self.map.swap(key, value);
+        self.emit(Swap(event_key, event_value, old.clone()));
+        old
+    }
+
+    pub fn pop(&mut self, key: &K) -> Option<V>
+    {
+        let old = self.map.pop(key);
+        match old {
+            Some(ref v) if self.observed() => self.emit(Pop(key.clone(), v.clone())),
+            _ => ()
+        }
+        old
+    }
+
+    pub fn clear(&mut self)
+    {
+        self.map.clear();
+        if self.observed() {
+            self.emit(Clear);
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use btree::BTreeMap;
+
+// fold every entry of the map using up to tasks tasks, the result is
+// that of folding them all in key order starting from init. The first
+// part starts from init and every other part from identity, which
+// combine has to leave unchanged, then the parts are combined in key
+// order.
+pub fn par_fold<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share, A: Clone+Send>
+    (map: &BTreeMap<K, V>, tasks: uint, init: A, identity: A, fold: fn(A, &K, &V) -> A, combine: fn(A, A) -> A) -> A
+{
+    let parts = map.split_subtrees(tasks);
+    let count = parts.len();
+    let (tx, rx) = channel();
+
+    for (i, part) in parts.move_iter().enumerate() {
+        let tx = tx.clone();
+        let start = if i == 0 { init.clone() } else { identity.clone() };
+        spawn(proc() {
+            let mut acc = start;
+            for (k, v) in part.iter() {
+                acc = fold(acc, k, v);
+            }
+            tx.send((i, acc));
+        });
+    }
+
+    let mut results: Vec<Option<A>> = Vec::from_fn(count, |_| None);
+    for _ in range(0, count) {
+        let (i, acc) = rx.recv();
+        *results.get_mut(i) = Some(acc);
+    }
+
+    let mut results = results.move_iter().map(|acc| acc.unwrap());
+    match results.next() {
+        Some(first) => results.fold(first, |a, b| combine(a, b)),
+        None => init
+    }
+}
+
+// map every entry and reduce the results, None if the map is empty
+pub fn par_map_reduce<K: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share, R: Send>
+    (map: &BTreeMap<K, V>, tasks: uint, f: fn(&K, &V) -> R, reduce: fn(R, R) -> R) -> Option<R>
+{
+    let parts = map.split_subtrees(tasks);
+    let count = parts.len();
+    let (tx, rx) = channel();
+
+    for (i, part) in parts.move_iter().enumerate() {
+        let tx = tx.clone();
+        spawn(proc() {
+            let mut acc = None;
+            for (k, v) in part.iter() {
+                let r = f(k, v);
+                acc = Some(match acc {
+                    Some(acc) => reduce(acc, r),
+                    None => r
+                });
+            }
+            tx.send((i, acc));
+        });
+    }
+
+    let mut results: Vec<Option<R>> = Vec::from_fn(count, |_| None);
+    for _ in range(0, count) {
+        let (i, acc) = rx.recv();
+        *results.get_mut(i) = acc;
+    }
+
+    results.move_iter().fold(None, |a, b| {
+        match (a, b) {
+            (Some(a), Some(b)) => Some(reduce(a, b)),
+            (a, None) => a,
+            (None, b) => b
+        }
+    })
+}
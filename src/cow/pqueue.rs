@@ -0,0 +1,139 @@
+use std::mem;
+use std::default::Default;
+
+use btree::BTreeMap;
+
+// A priority queue kept in a BTreeMap ordered by (priority, sequence
+// number), so equal priorities come out in the order they were pushed.
+// The smallest and largest entries are found down the edges of the
+// tree, and clones share their nodes, so a snapshot of the queue costs
+// O(1).
+pub struct CowPriorityQueue<P, T> {
+    queue: BTreeMap<(P, uint), T>,
+    // sequence number -> priority, to find an entry from its handle
+    priorities: BTreeMap<uint, P>,
+    next_seq: uint
+}
+
+// refers to a pushed entry until it is popped or removed
+#[deriving(Clone, Eq)]
+pub struct Handle {
+    seq: uint
+}
+
+impl<P: Default+Clone+TotalOrd+Send+Share, T: Default+Clone+Send+Share> CowPriorityQueue<P, T>
+{
+    pub fn new() -> CowPriorityQueue<P, T>
+    {
+        CowPriorityQueue {
+            queue: BTreeMap::new(),
+            priorities: BTreeMap::new(),
+            next_seq: 0
+        }
+    }
+
+    pub fn push(&mut self, priority: P, value: T) -> Handle
+    {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.priorities.insert(seq, priority.clone());
+        self.queue.insert((priority, seq), value);
+        Handle {seq: seq}
+    }
+
+    pub fn peek_min<'a>(&'a self) -> Option<(&'a P, &'a T)>
+    {
+        match self.queue.first() {
+            Some((&(ref p, _), value)) => Some((p, value)),
+            None => None
+        }
+    }
+
+    pub fn peek_max<'a>(&'a self) -> Option<(&'a P, &'a T)>
+    {
+        match self.queue.last() {
+            Some((&(ref p, _), value)) => Some((p, value)),
+            None => None
+        }
+    }
+
+    pub fn pop_min(&mut self) -> Option<(P, T)>
+    {
+        let key = match self.queue.first() {
+            Some((key, _)) => key.clone(),
+            None => return None
+        };
+        self.take(key)
+    }
+
+    pub fn pop_max(&mut self) -> Option<(P, T)>
+    {
+        let key = match self.queue.last() {
+            Some((key, _)) => key.clone(),
+            None => return None
+        };
+        self.take(key)
+    }
+
+    fn take(&mut self, key: (P, uint)) -> Option<(P, T)>
+    {
+        let value = self.queue.pop(&key).unwrap();
+        let (priority, seq) = key;
+        self.priorities.remove(&seq);
+        Some((priority, value))
+    }
+
+    pub fn priority<'a>(&'a self, handle: &Handle) -> Option<&'a P>
+    {
+        self.priorities.find(&handle.seq)
+    }
+
+    // false if the entry is no longer queued. Among equal priorities the
+    // entry keeps its place from when it was pushed.
+    pub fn change_priority(&mut self, handle: &Handle, priority: P) -> bool
+    {
+        let old = match self.priorities.find_mut(&handle.seq) {
+            Some(p) => mem::replace(p, priority.clone()),
+            None => return false
+        };
+        let value = self.queue.pop(&(old, handle.seq)).unwrap();
+        self.queue.insert((priority, handle.seq), value);
+        true
+    }
+
+    pub fn remove(&mut self, handle: &Handle) -> Option<(P, T)>
+    {
+        let key = match self.priorities.find(&handle.seq) {
+            Some(p) => (p.clone(), handle.seq),
+            None => return None
+        };
+        self.take(key)
+    }
+}
+
+impl<P: Default+Clone+TotalOrd+Send+Share, T: Default+Clone+Send+Share> Container for CowPriorityQueue<P, T> {
+    fn len(&self) -> uint
+    {
+        self.queue.len()
+    }
+}
+
+impl<P: Default+Clone+TotalOrd+Send+Share, T: Default+Clone+Send+Share> Clone for CowPriorityQueue<P, T>
+{
+    fn clone(&self) -> CowPriorityQueue<P, T>
+    {
+        CowPriorityQueue {
+            queue: self.queue.clone(),
+            priorities: self.priorities.clone(),
+            next_seq: self.next_seq
+        }
+    }
+}
+
+impl<P: Default+Clone+TotalOrd+Send+Share, T: Default+Clone+Send+Share> Default for CowPriorityQueue<P, T>
+{
+    fn default() -> CowPriorityQueue<P, T>
+    {
+        CowPriorityQueue::new()
+    }
+}
@@ -0,0 +1,312 @@
+use std::default::Default;
+
+use btree::{BTreeMap, BTreeMapIterator};
+
+// Disjoint half-open ranges [start, end) with a value each, stored by
+// start. Inserting over existing ranges replaces the overlapped parts,
+// and ranges that touch and hold equal values are joined into one.
+// Removing the middle of a range splits it in two. Clones share their
+// nodes like BTreeMap does.
+pub struct RangeMap<T, V> {
+    // start -> (end, value)
+    map: BTreeMap<T, (T, V)>
+}
+
+pub struct RangeMapIterator<'a, T, V> {
+    iter: BTreeMapIterator<'a, T, (T, V)>
+}
+
+impl<T: Default+Clone+TotalOrd+Send+Share, V: Eq+Default+Clone+Send+Share> RangeMap<T, V>
+{
+    pub fn new() -> RangeMap<T, V>
+    {
+        RangeMap {
+            map: BTreeMap::new()
+        }
+    }
+
+    // number of disjoint ranges
+    pub fn len(&self) -> uint
+    {
+        self.map.len()
+    }
+
+    // the range with the greatest start <= point
+    fn floor<'a>(&'a self, point: &T) -> Option<(&'a T, &'a (T, V))>
+    {
+        match self.map.rank_by(|s| s.cmp(point) != Greater) {
+            0 => None,
+            n => self.map.select(n - 1)
+        }
+    }
+
+    // the range with the greatest start < point
+    fn before<'a>(&'a self, point: &T) -> Option<(&'a T, &'a (T, V))>
+    {
+        match self.map.rank(point) {
+            0 => None,
+            n => self.map.select(n - 1)
+        }
+    }
+
+    pub fn get<'a>(&'a self, point: &T) -> Option<&'a V>
+    {
+        match self.floor(point) {
+            Some((_, &(ref end, ref value))) if end.cmp(point) == Greater => Some(value),
+            _ => None
+        }
+    }
+
+    pub fn contains(&self, point: &T) -> bool
+    {
+        self.get(point).is_some()
+    }
+
+    // an empty range changes nothing
+    pub fn insert_range(&mut self, start: T, end: T, value: V)
+    {
+        if start.cmp(&end) != Less {
+            return;
+        }
+        self.remove_range(&start, &end);
+
+        let mut start = start;
+        let mut end = end;
+
+        // join the neighbours that touch the range and hold the same value
+        let left = match self.before(&start) {
+            Some((s, &(ref e, ref v))) if e.cmp(&start) == Equal && *v == value => Some(s.clone()),
+            _ => None
+        };
+        match left {
+            Some(s) => {
+                self.map.remove(&s);
+                start = s;
+            },
+            None => ()
+        }
+
+        let right = match self.map.find(&end) {
+            Some(&(ref e, ref v)) if *v == value => Some(e.clone()),
+            _ => None
+        };
+        match right {
+            Some(e) => {
+                self.map.remove(&end);
+                end = e;
+            },
+            None => ()
+        }
+
+        self.map.insert(start, (end, value));
+    }
+
+    pub fn remove_range(&mut self, start: &T, end: &T)
+    {
+        if start.cmp(end) != Less {
+            return;
+        }
+
+        // a range starting before start is cut there, the part after end
+        // is kept if it reaches that far
+        let before = match self.before(start) {
+            Some((s, &(ref e, ref v))) if e.cmp(start) == Greater => Some((s.clone(), e.clone(), v.clone())),
+            _ => None
+        };
+        match before {
+            Some((s, e, v)) => {
+                if e.cmp(end) == Greater {
+                    self.map.insert(end.clone(), (e, v.clone()));
+                }
+                self.map.insert(s, (start.clone(), v));
+            },
+            None => ()
+        }
+
+        // the ranges starting inside are dropped, only the last one can
+        // reach past end
+        let inside: Vec<(T, T)> = self.map.range(Some(start), Some(end))
+            .map(|(s, &(ref e, _))| (s.clone(), e.clone())).collect();
+        for (s, e) in inside.move_iter() {
+            let (_, v) = self.map.pop(&s).unwrap();
+            if e.cmp(end) == Greater {
+                self.map.insert(end.clone(), (e, v));
+            }
+        }
+    }
+
+    // the parts of [lo, hi) not covered by any range, in order
+    pub fn gaps<'a>(&'a self, lo: &T, hi: &T) -> Gaps<'a, T, V>
+    {
+        let pos = match self.floor(lo) {
+            Some((_, &(ref end, _))) if end.cmp(lo) == Greater => end.clone(),
+            _ => lo.clone()
+        };
+        let mut iter = self.map.iter();
+        iter.seek(&pos);
+        Gaps {
+            iter: iter,
+            pos: pos,
+            hi: hi.clone()
+        }
+    }
+
+    // (start, end, value) of every range in order
+    pub fn iter<'a>(&'a self) -> RangeMapIterator<'a, T, V>
+    {
+        RangeMapIterator {
+            iter: self.map.iter()
+        }
+    }
+}
+
+impl<'a, T: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share> Iterator<(&'a T, &'a T, &'a V)> for RangeMapIterator<'a, T, V>
+{
+    fn next(&mut self) -> Option<(&'a T, &'a T, &'a V)>
+    {
+        match self.iter.next() {
+            Some((start, &(ref end, ref value))) => Some((start, end, value)),
+            None => None
+        }
+    }
+}
+
+pub struct Gaps<'a, T, V> {
+    iter: BTreeMapIterator<'a, T, (T, V)>,
+    // everything before pos is covered or already returned
+    pos: T,
+    hi: T
+}
+
+impl<'a, T: Default+Clone+TotalOrd+Send+Share, V: Default+Clone+Send+Share> Iterator<(T, T)> for Gaps<'a, T, V>
+{
+    fn next(&mut self) -> Option<(T, T)>
+    {
+        loop {
+            if self.pos.cmp(&self.hi) != Less {
+                return None;
+            }
+
+            match self.iter.next() {
+                Some((start, &(ref end, _))) => {
+                    let gap = if start.cmp(&self.pos) == Greater {
+                        let gap_end = if start.cmp(&self.hi) == Less { start } else { &self.hi };
+                        Some((self.pos.clone(), gap_end.clone()))
+                    } else {
+                        None
+                    };
+                    self.pos = end.clone();
+                    if gap.is_some() {
+                        return gap;
+                    }
+                },
+                None => {
+                    let gap = (self.pos.clone(), self.hi.clone());
+                    self.pos = self.hi.clone();
+                    return Some(gap);
+                }
+            }
+        }
+    }
+}
+
+impl<T: Default+Clone+TotalOrd+Send+Share, V: Eq+Default+Clone+Send+Share> Clone for RangeMap<T, V>
+{
+    fn clone(&self) -> RangeMap<T, V>
+    {
+        RangeMap {
+            map: self.map.clone()
+        }
+    }
+}
+
+impl<T: Default+Clone+TotalOrd+Send+Share, V: Eq+Default+Clone+Send+Share> Default for RangeMap<T, V>
+{
+    fn default() -> RangeMap<T, V>
+    {
+        RangeMap::new()
+    }
+}
+
+// Disjoint half-open ranges, overlapping or touching ranges are joined
+pub struct RangeSet<T> {
+    map: RangeMap<T, ()>
+}
+
+pub struct RangeSetIterator<'a, T> {
+    iter: RangeMapIterator<'a, T, ()>
+}
+
+impl<T: Default+Clone+TotalOrd+Send+Share> RangeSet<T>
+{
+    pub fn new() -> RangeSet<T>
+    {
+        RangeSet {
+            map: RangeMap::new()
+        }
+    }
+
+    // number of disjoint ranges
+    pub fn len(&self) -> uint
+    {
+        self.map.len()
+    }
+
+    pub fn contains(&self, point: &T) -> bool
+    {
+        self.map.contains(point)
+    }
+
+    pub fn insert_range(&mut self, start: T, end: T)
+    {
+        self.map.insert_range(start, end, ())
+    }
+
+    pub fn remove_range(&mut self, start: &T, end: &T)
+    {
+        self.map.remove_range(start, end)
+    }
+
+    // the parts of [lo, hi) not in the set, in order
+    pub fn gaps<'a>(&'a self, lo: &T, hi: &T) -> Gaps<'a, T, ()>
+    {
+        self.map.gaps(lo, hi)
+    }
+
+    // (start, end) of every range in order
+    pub fn iter<'a>(&'a self) -> RangeSetIterator<'a, T>
+    {
+        RangeSetIterator {
+            iter: self.map.iter()
+        }
+    }
+}
+
+impl<'a, T: Default+Clone+TotalOrd+Send+Share> Iterator<(&'a T, &'a T)> for RangeSetIterator<'a, T>
+{
+    fn next(&mut self) -> Option<(&'a T, &'a T)>
+    {
+        match self.iter.next() {
+            Some((start, end, _)) => Some((start, end)),
+            None => None
+        }
+    }
+}
+
+impl<T: Default+Clone+TotalOrd+Send+Share> Clone for RangeSet<T>
+{
+    fn clone(&self) -> RangeSet<T>
+    {
+        RangeSet {
+            map: self.map.clone()
+        }
+    }
+}
+
+impl<T: Default+Clone+TotalOrd+Send+Share> Default for RangeSet<T>
+{
+    fn default() -> RangeSet<T>
+    {
+        RangeSet::new()
+    }
+}
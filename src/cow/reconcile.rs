@@ -0,0 +1,313 @@
+use std::hash::{Hash, hash};
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::default::Default;
+
+use btree;
+use btree::SummaryMap;
+
+// ranges holding at most this many entries are exchanged wholesale
+// instead of being split any further
+static ENTRIES_THRESHOLD: uint = 16;
+
+// number of sub ranges a mismatched range is split into
+static FANOUT: uint = 8;
+
+// half open range [lo, hi) of keys, None is unbounded
+#[deriving(Clone)]
+pub struct KeyRange<K> {
+    pub lo: Option<K>,
+    pub hi: Option<K>
+}
+
+impl<K: TotalOrd> KeyRange<K>
+{
+    pub fn full() -> KeyRange<K>
+    {
+        KeyRange {lo: None, hi: None}
+    }
+
+    fn above_lo(&self, key: &K) -> bool
+    {
+        match self.lo {
+            Some(ref lo) => key.cmp(lo) != Less,
+            None => true
+        }
+    }
+
+    fn below_hi(&self, key: &K) -> bool
+    {
+        match self.hi {
+            Some(ref hi) => key.cmp(hi) == Less,
+            None => true
+        }
+    }
+
+    pub fn contains(&self, key: &K) -> bool
+    {
+        self.above_lo(key) && self.below_hi(key)
+    }
+}
+
+// order independent digest of the entries in a range, two replicas
+// holding the same entries for a range produce the same fingerprint.
+// As a node summary every internal node caches it per child, so the
+// fingerprint of a range costs a descent instead of a walk.
+#[deriving(Clone, Eq)]
+pub struct Fingerprint {
+    pub count: uint,
+    pub hash: u64
+}
+
+impl Fingerprint
+{
+    fn new() -> Fingerprint
+    {
+        Fingerprint {count: 0, hash: 0}
+    }
+
+    fn add<K: Hash, V: Hash>(&mut self, key: &K, value: &V)
+    {
+        self.count += 1;
+        self.hash += hash(&(key, value));
+    }
+}
+
+impl Default for Fingerprint
+{
+    fn default() -> Fingerprint
+    {
+        Fingerprint::new()
+    }
+}
+
+// the message variant below is also called Summary
+impl<K: Hash, V: Hash> btree::Summary<K, V> for Fingerprint
+{
+    fn summarize(key: &K, value: &V) -> Fingerprint
+    {
+        let mut fp = Fingerprint::new();
+        fp.add(key, value);
+        fp
+    }
+
+    fn combine(&mut self, other: &Fingerprint)
+    {
+        self.count += other.count;
+        self.hash += other.hash;
+    }
+}
+
+pub enum SyncMessage<K, V> {
+    // the sender's fingerprint of a range
+    Summary(KeyRange<K>, Fingerprint),
+    // every entry the sender holds in a range, if the flag is set
+    // the receiver answers with its own entries for the range
+    Entries(KeyRange<K>, Vec<(K, V)>, bool)
+}
+
+// A batch of messages is the unit of exchange. Each side answers a
+// non-empty batch with exactly one batch, once either side sends an
+// empty batch both sides are in sync.
+pub trait Transport<K, V> {
+    fn send(&mut self, batch: Vec<SyncMessage<K, V>>);
+    fn recv(&mut self) -> Option<Vec<SyncMessage<K, V>>>;
+}
+
+pub struct SyncSession<'a, K, V> {
+    map: &'a SummaryMap<K, V, Fingerprint>,
+    outgoing: Vec<(K, V)>,
+    done: bool
+}
+
+impl<'a, K: Default+Clone+TotalOrd+Send+Share+Hash, V: Default+Clone+Send+Share+Hash+Eq> SyncSession<'a, K, V>
+{
+    pub fn new(map: &'a SummaryMap<K, V, Fingerprint>) -> SyncSession<'a, K, V>
+    {
+        SyncSession {
+            map: map,
+            outgoing: Vec::new(),
+            done: false
+        }
+    }
+
+    // send the opening summary, only one side of a session should start
+    pub fn start<T: Transport<K, V>>(&mut self, transport: &mut T)
+    {
+        let range = KeyRange::full();
+        let fp = self.fingerprint(&range);
+        transport.send(vec!(Summary(range, fp)));
+    }
+
+    // handle at most one batch from the transport, returns false if
+    // there was nothing to do
+    pub fn poll<T: Transport<K, V>>(&mut self, transport: &mut T) -> bool
+    {
+        if self.done {
+            return false;
+        }
+
+        match transport.recv() {
+            None => false,
+            Some(batch) => {
+                if batch.is_empty() {
+                    self.done = true;
+                } else {
+                    let reply = self.receive(batch);
+                    self.done = reply.is_empty();
+                    transport.send(reply);
+                }
+                true
+            }
+        }
+    }
+
+    pub fn is_done(&self) -> bool
+    {
+        self.done
+    }
+
+    // entries that the remote side is missing or holds a different
+    // value for, only complete once the session is done
+    pub fn outgoing<'b>(&'b self) -> &'b [(K, V)]
+    {
+        self.outgoing.as_slice()
+    }
+
+    pub fn unwrap(self) -> Vec<(K, V)>
+    {
+        self.outgoing
+    }
+
+    fn receive(&mut self, batch: Vec<SyncMessage<K, V>>) -> Vec<SyncMessage<K, V>>
+    {
+        let mut reply = Vec::new();
+
+        for msg in batch.move_iter() {
+            match msg {
+                Summary(range, theirs) => {
+                    let mine = self.fingerprint(&range);
+                    if mine != theirs {
+                        self.split(range, mine.count, &mut reply);
+                    }
+                },
+                Entries(range, theirs, respond) => {
+                    let mine = self.entries(&range);
+                    self.compare(mine.as_slice(), theirs.as_slice());
+                    if respond {
+                        reply.push(Entries(range, mine, false));
+                    }
+                }
+            }
+        }
+
+        reply
+    }
+
+    fn entries(&self, range: &KeyRange<K>) -> Vec<(K, V)>
+    {
+        self.map.range(range.lo.as_ref(), range.hi.as_ref())
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect()
+    }
+
+    fn fingerprint(&self, range: &KeyRange<K>) -> Fingerprint
+    {
+        self.map.fold_range(range.lo.as_ref(), range.hi.as_ref())
+    }
+
+    // answer a mismatched range holding count of our entries, either
+    // with the entries or by splitting it by rank into sub ranges along
+    // our own keys
+    fn split(&self, range: KeyRange<K>, count: uint, reply: &mut Vec<SyncMessage<K, V>>)
+    {
+        if count <= ENTRIES_THRESHOLD {
+            let entries = self.entries(&range);
+            reply.push(Entries(range, entries, true));
+            return;
+        }
+
+        let start = match range.lo {
+            Some(ref lo) => self.map.rank(lo),
+            None => 0
+        };
+        let chunk = (count + FANOUT - 1) / FANOUT;
+        let mut lo = range.lo;
+        let mut idx = chunk;
+        loop {
+            let hi = if idx < count {
+                let (k, _) = self.map.select(start + idx).unwrap();
+                Some(k.clone())
+            } else {
+                range.hi.clone()
+            };
+
+            let sub = KeyRange {lo: lo, hi: hi.clone()};
+            let fp = self.fingerprint(&sub);
+            reply.push(Summary(sub, fp));
+            if idx >= count {
+                return;
+            }
+            lo = hi;
+            idx += chunk;
+        }
+    }
+
+    // remember every entry of ours the remote lacks or disagrees on
+    fn compare(&mut self, mine: &[(K, V)], theirs: &[(K, V)])
+    {
+        let mut theirs = theirs.iter().peekable();
+
+        for &(ref key, ref value) in mine.iter() {
+            loop {
+                let ord = match theirs.peek() {
+                    Some(&&(ref k, _)) => k.cmp(key),
+                    None => Greater
+                };
+
+                match ord {
+                    Less => { theirs.next(); },
+                    Equal => {
+                        let &(_, ref v) = theirs.next().unwrap();
+                        if *v != *value {
+                            self.outgoing.push((key.clone(), value.clone()));
+                        }
+                        break;
+                    },
+                    Greater => {
+                        self.outgoing.push((key.clone(), value.clone()));
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+// in process transport, both ends are owned by the same task
+pub struct Loopback<K, V> {
+    inbox: Rc<RefCell<Vec<Vec<SyncMessage<K, V>>>>>,
+    outbox: Rc<RefCell<Vec<Vec<SyncMessage<K, V>>>>>
+}
+
+pub fn loopback<K, V>() -> (Loopback<K, V>, Loopback<K, V>)
+{
+    let a = Rc::new(RefCell::new(Vec::new()));
+    let b = Rc::new(RefCell::new(Vec::new()));
+
+    (Loopback {inbox: a.clone(), outbox: b.clone()},
+     Loopback {inbox: b, outbox: a})
+}
+
+impl<K, V> Transport<K, V> for Loopback<K, V>
+{
+    fn send(&mut self, batch: Vec<SyncMessage<K, V>>)
+    {
+        self.outbox.borrow_mut().push(batch);
+    }
+
+    fn recv(&mut self) -> Option<Vec<SyncMessage<K, V>>>
+    {
+        self.inbox.borrow_mut().shift()
+    }
+}
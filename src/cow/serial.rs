@@ -0,0 +1,199 @@
+use serialize::{Encodable, Decodable, Encoder, Decoder};
+
+use btree::{BTreeMap, BTreeSet};
+
+// what to do when decoded input holds the same key more than once
+pub enum DuplicatePolicy {
+    RejectDuplicates,
+    KeepFirst,
+    KeepLast
+}
+
+pub enum DecodeError<E> {
+    DecoderError(E),
+    // index of the offending entry in the input
+    DuplicateKey(uint)
+}
+
+impl<E, S: Encoder<E>, K: Encodable<S, E>+Default+Clone+TotalOrd+Send+Share, V: Encodable<S, E>+Default+Clone+Send+Share>
+    Encodable<S, E> for BTreeMap<K, V>
+{
+    fn encode(&self, s: &mut S) -> Result<(), E>
+    {
+        s.emit_map(self.len(), |s| {
+            for (i, (k, v)) in self.iter().enumerate() {
+                try!(s.emit_map_elt_key(i, |s| k.encode(s)));
+                try!(s.emit_map_elt_val(i, |s| v.encode(s)));
+            }
+            Ok(())
+        })
+    }
+}
+
+impl<E, S: Encoder<E>, T: Encodable<S, E>+Default+Clone+TotalOrd+Send+Share>
+    Encodable<S, E> for BTreeSet<T>
+{
+    fn encode(&self, s: &mut S) -> Result<(), E>
+    {
+        s.emit_seq(self.len(), |s| {
+            for (i, v) in self.iter().enumerate() {
+                try!(s.emit_seq_elt(i, |s| v.encode(s)));
+            }
+            Ok(())
+        })
+    }
+}
+
+// later entries replace earlier ones, like repeated inserts would
+impl<E, D: Decoder<E>, K: Decodable<D, E>+Default+Clone+TotalOrd+Send+Share, V: Decodable<D, E>+Default+Clone+Send+Share>
+    Decodable<D, E> for BTreeMap<K, V>
+{
+    fn decode(d: &mut D) -> Result<BTreeMap<K, V>, E>
+    {
+        match decode_map(d, KeepLast) {
+            Ok(map) => Ok(map),
+            Err(DecoderError(e)) => Err(e),
+            Err(DuplicateKey(_)) => fail!("duplicates are kept")
+        }
+    }
+}
+
+impl<E, D: Decoder<E>, T: Decodable<D, E>+Default+Clone+TotalOrd+Send+Share>
+    Decodable<D, E> for BTreeSet<T>
+{
+    fn decode(d: &mut D) -> Result<BTreeSet<T>, E>
+    {
+        match decode_set(d, KeepLast) {
+            Ok(set) => Ok(set),
+            Err(DecoderError(e)) => Err(e),
+            Err(DuplicateKey(_)) => fail!("duplicates are kept")
+        }
+    }
+}
+
+pub fn decode_map<E, D: Decoder<E>, K: Decodable<D, E>+Default+Clone+TotalOrd+Send+Share, V: Decodable<D, E>+Default+Clone+Send+Share>
+    (d: &mut D, policy: DuplicatePolicy) -> Result<BTreeMap<K, V>, DecodeError<E>>
+{
+    let entries = d.read_map(|d, len| {
+        let mut entries = Vec::with_capacity(len);
+        for i in range(0, len) {
+            let key = try!(d.read_map_elt_key(i, |d| Decodable::decode(d)));
+            let value = try!(d.read_map_elt_val(i, |d| Decodable::decode(d)));
+            entries.push((key, value));
+        }
+        Ok(entries)
+    });
+
+    let entries = match entries {
+        Ok(entries) => entries,
+        Err(e) => return Err(DecoderError(e))
+    };
+
+    match dedup_sorted(entries, policy) {
+        Sorted(entries) => Ok(BTreeMap::from_sorted_vec(entries)),
+        Unsorted(entries) => {
+            let mut map = BTreeMap::new();
+            match policy {
+                RejectDuplicates => {
+                    for (i, (key, value)) in entries.move_iter().enumerate() {
+                        if map.insert(key, value) {
+                            return Err(DuplicateKey(i));
+                        }
+                    }
+                },
+                // inserted backwards the first of the duplicates is the
+                // one that is kept
+                KeepFirst => {
+                    for (key, value) in entries.move_iter().rev() {
+                        map.insert(key, value);
+                    }
+                },
+                KeepLast => {
+                    for (key, value) in entries.move_iter() {
+                        map.insert(key, value);
+                    }
+                }
+            }
+            Ok(map)
+        },
+        Duplicate(i) => Err(DuplicateKey(i))
+    }
+}
+
+pub fn decode_set<E, D: Decoder<E>, T: Decodable<D, E>+Default+Clone+TotalOrd+Send+Share>
+    (d: &mut D, policy: DuplicatePolicy) -> Result<BTreeSet<T>, DecodeError<E>>
+{
+    let values = d.read_seq(|d, len| {
+        let mut values = Vec::with_capacity(len);
+        for i in range(0, len) {
+            let value = try!(d.read_seq_elt(i, |d| Decodable::decode(d)));
+            values.push((value, ()));
+        }
+        Ok(values)
+    });
+
+    let values = match values {
+        Ok(values) => values,
+        Err(e) => return Err(DecoderError(e))
+    };
+
+    match dedup_sorted(values, policy) {
+        Sorted(values) => {
+            Ok(BTreeSet::from_sorted_vec(values.move_iter().map(|(v, _)| v).collect()))
+        },
+        Unsorted(values) => {
+            let mut set = BTreeSet::new();
+            for (i, (value, _)) in values.move_iter().enumerate() {
+                if set.insert(value) {
+                    match policy {
+                        RejectDuplicates => return Err(DuplicateKey(i)),
+                        KeepFirst | KeepLast => ()
+                    }
+                }
+            }
+            Ok(set)
+        },
+        Duplicate(i) => Err(DuplicateKey(i))
+    }
+}
+
+enum Prepared<K, V> {
+    Sorted(Vec<(K, V)>),
+    Unsorted(Vec<(K, V)>),
+    Duplicate(uint)
+}
+
+// if the input is already ordered resolve the duplicates (which can
+// only be neighbours) so the result can be bulk loaded
+fn dedup_sorted<K: TotalOrd, V>(entries: Vec<(K, V)>, policy: DuplicatePolicy) -> Prepared<K, V>
+{
+    for i in range(1, entries.len()) {
+        let &(ref prev, _) = entries.get(i-1);
+        let &(ref key, _) = entries.get(i);
+        if prev.cmp(key) == Greater {
+            return Unsorted(entries);
+        }
+    }
+
+    let mut out: Vec<(K, V)> = Vec::with_capacity(entries.len());
+    for (i, (key, value)) in entries.move_iter().enumerate() {
+        let duplicate = match out.last() {
+            Some(&(ref prev, _)) => prev.cmp(&key) == Equal,
+            None => false
+        };
+
+        if duplicate {
+            match policy {
+                RejectDuplicates => return Duplicate(i),
+                KeepFirst => (),
+                KeepLast => {
+                    out.pop();
+                    out.push((key, value));
+                }
+            }
+        } else {
+            out.push((key, value));
+        }
+    }
+    Sorted(out)
+}
@@ -0,0 +1,213 @@
+use std::io;
+use std::io::{IoResult, IoError, Reader, Writer};
+use std::str;
+use std::cmp;
+use std::uint;
+use std::int;
+
+use btree::BTreeMap;
+use crc::Crc32;
+
+// Layout of a snapshot, all integers are little endian
+//
+//   magic "COWS", version u32
+//   root node
+//   crc32 u32 of everything before it
+//
+// each node is a tag byte (0 empty, 1 leaf, 2 internal) followed by
+//
+//   leaf:     used u32, keys[used], values[used]
+//   internal: used u32, total_len u64, keys[used-1], children[used]
+//
+// so a snapshot can only be read back by a build with the same
+// node sizes, the version is bumped whenever the layout changes.
+
+static MAGIC: &'static [u8] = bytes!("COWS");
+pub static SNAPSHOT_VERSION: u32 = 1;
+
+// lengths in the input are read before the checksum can be checked, the
+// bytes they count are read this many at a time so a corrupt length
+// runs into the end of the input before much is allocated
+static READ_CHUNK: uint = 64 * 1024;
+
+// types that can be stored in a snapshot
+pub trait SnapshotItem {
+    fn write_item<W: Writer>(&self, w: &mut W) -> IoResult<()>;
+    fn read_item<R: Reader>(r: &mut R) -> IoResult<Self>;
+}
+
+pub fn invalid(desc: &'static str) -> IoError
+{
+    IoError {
+        kind: io::InvalidInput,
+        desc: desc,
+        detail: None
+    }
+}
+
+// len bytes, the buffer only grows as they arrive
+fn read_counted<R: Reader>(r: &mut R, len: uint) -> IoResult<Vec<u8>>
+{
+    let mut buf = Vec::with_capacity(cmp::min(len, READ_CHUNK));
+    while buf.len() < len {
+        let chunk = try!(r.read_bytes(cmp::min(len - buf.len(), READ_CHUNK)));
+        buf.push_all(chunk.as_slice());
+    }
+    Ok(buf)
+}
+
+pub fn write_snapshot<W: Writer, K: SnapshotItem+Default+Clone+TotalOrd+Send+Share, V: SnapshotItem+Default+Clone+Send+Share>
+    (map: &BTreeMap<K, V>, w: &mut W) -> IoResult<()>
+{
+    let crc = {
+        let mut w = ChecksumWriter {inner: &mut *w, crc: Crc32::new()};
+        try!(w.write(MAGIC));
+        try!(w.write_le_u32(SNAPSHOT_VERSION));
+        try!(map.write_nodes(&mut w));
+        w.crc.finish()
+    };
+    w.write_le_u32(crc)
+}
+
+pub fn read_snapshot<R: Reader, K: SnapshotItem+Default+Clone+TotalOrd+Send+Share, V: SnapshotItem+Default+Clone+Send+Share>
+    (r: &mut R) -> IoResult<BTreeMap<K, V>>
+{
+    let (map, crc) = {
+        let mut r = ChecksumReader {inner: &mut *r, crc: Crc32::new()};
+        let magic = try!(r.read_bytes(MAGIC.len()));
+        if magic.as_slice() != MAGIC {
+            return Err(invalid("not a snapshot"));
+        }
+        if try!(r.read_le_u32()) != SNAPSHOT_VERSION {
+            return Err(invalid("unsupported snapshot version"));
+        }
+        let map = try!(BTreeMap::read_nodes(&mut r));
+        (map, r.crc.finish())
+    };
+
+    if try!(r.read_le_u32()) != crc {
+        return Err(invalid("snapshot checksum mismatch"));
+    }
+    Ok(map)
+}
+
+struct ChecksumWriter<'a, W> {
+    inner: &'a mut W,
+    crc: Crc32
+}
+
+impl<'a, W: Writer> Writer for ChecksumWriter<'a, W>
+{
+    fn write(&mut self, buf: &[u8]) -> IoResult<()>
+    {
+        self.crc.update(buf);
+        self.inner.write(buf)
+    }
+}
+
+struct ChecksumReader<'a, R> {
+    inner: &'a mut R,
+    crc: Crc32
+}
+
+impl<'a, R: Reader> Reader for ChecksumReader<'a, R>
+{
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<uint>
+    {
+        let len = try!(self.inner.read(buf));
+        self.crc.update(buf.slice_to(len));
+        Ok(len)
+    }
+}
+
+macro_rules! snapshot_item(
+    ($t:ty, $write:ident, $read:ident) => (
+        impl SnapshotItem for $t {
+            fn write_item<W: Writer>(&self, w: &mut W) -> IoResult<()> { w.$write(*self) }
+            fn read_item<R: Reader>(r: &mut R) -> IoResult<$t> { r.$read() }
+        }
+    )
+)
+
+snapshot_item!(u8, write_u8, read_u8)
+snapshot_item!(u16, write_le_u16, read_le_u16)
+snapshot_item!(u32, write_le_u32, read_le_u32)
+snapshot_item!(u64, write_le_u64, read_le_u64)
+snapshot_item!(i8, write_i8, read_i8)
+snapshot_item!(i16, write_le_i16, read_le_i16)
+snapshot_item!(i32, write_le_i32, read_le_i32)
+snapshot_item!(i64, write_le_i64, read_le_i64)
+snapshot_item!(f32, write_le_f32, read_le_f32)
+snapshot_item!(f64, write_le_f64, read_le_f64)
+
+// uint and int are always stored as 64 bits so snapshots can move
+// between 32 and 64 bit machines, values that do not fit are rejected
+impl SnapshotItem for uint {
+    fn write_item<W: Writer>(&self, w: &mut W) -> IoResult<()> { w.write_le_u64(*self as u64) }
+
+    fn read_item<R: Reader>(r: &mut R) -> IoResult<uint>
+    {
+        let value = try!(r.read_le_u64());
+        if value > uint::MAX as u64 {
+            return Err(invalid("value does not fit in a uint"));
+        }
+        Ok(value as uint)
+    }
+}
+
+impl SnapshotItem for int {
+    fn write_item<W: Writer>(&self, w: &mut W) -> IoResult<()> { w.write_le_i64(*self as i64) }
+
+    fn read_item<R: Reader>(r: &mut R) -> IoResult<int>
+    {
+        let value = try!(r.read_le_i64());
+        if value < int::MIN as i64 || value > int::MAX as i64 {
+            return Err(invalid("value does not fit in an int"));
+        }
+        Ok(value as int)
+    }
+}
+
+impl SnapshotItem for bool {
+    fn write_item<W: Writer>(&self, w: &mut W) -> IoResult<()> { w.write_u8(*self as u8) }
+    fn read_item<R: Reader>(r: &mut R) -> IoResult<bool> { Ok(try!(r.read_u8()) != 0) }
+}
+
+impl SnapshotItem for () {
+    fn write_item<W: Writer>(&self, _: &mut W) -> IoResult<()> { Ok(()) }
+    fn read_item<R: Reader>(_: &mut R) -> IoResult<()> { Ok(()) }
+}
+
+impl SnapshotItem for ~str {
+    fn write_item<W: Writer>(&self, w: &mut W) -> IoResult<()>
+    {
+        try!(w.write_le_u32(self.len() as u32));
+        w.write_str(*self)
+    }
+
+    fn read_item<R: Reader>(r: &mut R) -> IoResult<~str>
+    {
+        let len = try!(r.read_le_u32()) as uint;
+        let bytes = try!(read_counted(r, len));
+        match str::from_utf8(bytes.as_slice()) {
+            Some(s) => Ok(s.to_owned()),
+            None => Err(invalid("string is not utf8"))
+        }
+    }
+}
+
+impl<A: SnapshotItem, B: SnapshotItem> SnapshotItem for (A, B) {
+    fn write_item<W: Writer>(&self, w: &mut W) -> IoResult<()>
+    {
+        let &(ref a, ref b) = self;
+        try!(a.write_item(w));
+        b.write_item(w)
+    }
+
+    fn read_item<R: Reader>(r: &mut R) -> IoResult<(A, B)>
+    {
+        let a = try!(SnapshotItem::read_item(r));
+        let b = try!(SnapshotItem::read_item(r));
+        Ok((a, b))
+    }
+}
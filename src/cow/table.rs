@@ -0,0 +1,217 @@
+use std::any::{Any, AnyRefExt};
+use std::default::Default;
+
+use btree::{BTreeMap, BTreeSet};
+
+// Rows keyed by a primary key, plus any number of named secondary
+// indexes mapping a key computed from the row to the primary keys of
+// every row that has it. All maps are copy on write, so cloning a
+// table is a cheap snapshot of the rows and indexes together.
+
+pub struct Table<PK, Row> {
+    rows: BTreeMap<PK, Row>,
+    indexes: Vec<NamedIndex<PK, Row>>
+}
+
+struct NamedIndex<PK, Row> {
+    name: ~str,
+    index: ~SecondaryIndex<PK, Row>:Send+Share
+}
+
+// the indexes have different key types, the table only sees them
+// through this
+trait SecondaryIndex<PK, Row> {
+    fn add(&mut self, pk: &PK, row: &Row);
+    fn remove(&mut self, pk: &PK, row: &Row);
+    fn clone_index(&self) -> ~SecondaryIndex<PK, Row>:Send+Share;
+    fn as_any<'a>(&'a self) -> &'a Any;
+}
+
+struct Index<PK, Row, IK> {
+    key: fn(&Row) -> IK,
+    map: BTreeMap<IK, BTreeSet<PK>>
+}
+
+impl<PK: Default+Clone+TotalOrd+Send+Share+'static, Row: 'static, IK: Default+Clone+TotalOrd+Send+Share+'static>
+    SecondaryIndex<PK, Row> for Index<PK, Row, IK>
+{
+    fn add(&mut self, pk: &PK, row: &Row)
+    {
+        let ik = (self.key)(row);
+        match self.map.find_mut(&ik) {
+            Some(pks) => {
+                pks.insert(pk.clone());
+                return;
+            },
+            None => ()
+        }
+
+        let mut pks = BTreeSet::new();
+        pks.insert(pk.clone());
+        self.map.insert(ik, pks);
+    }
+
+    fn remove(&mut self, pk: &PK, row: &Row)
+    {
+        let ik = (self.key)(row);
+        let empty = match self.map.find_mut(&ik) {
+            Some(pks) => {
+                pks.remove(pk);
+                pks.is_empty()
+            },
+            None => false
+        };
+        if empty {
+            self.map.remove(&ik);
+        }
+    }
+
+    fn clone_index(&self) -> ~SecondaryIndex<PK, Row>:Send+Share
+    {
+        ~Index {
+            key: self.key,
+            map: self.map.clone()
+        } as ~SecondaryIndex<PK, Row>:Send+Share
+    }
+
+    fn as_any<'a>(&'a self) -> &'a Any
+    {
+        self as &Any
+    }
+}
+
+impl<PK: Default+Clone+TotalOrd+Send+Share+'static, Row: Default+Clone+Send+Share+'static> Table<PK, Row>
+{
+    pub fn new() -> Table<PK, Row>
+    {
+        Table {
+            rows: BTreeMap::new(),
+            indexes: Vec::new()
+        }
+    }
+
+    // declare an index on key(row), rows already in the table are indexed
+    // right away. false if there already is an index called name, it is
+    // left as it is.
+    pub fn add_index<IK: Default+Clone+TotalOrd+Send+Share+'static>(&mut self, name: &str, key: fn(&Row) -> IK) -> bool
+    {
+        if self.find_index(name).is_some() {
+            return false;
+        }
+
+        let mut index = Index {
+            key: key,
+            map: BTreeMap::new()
+        };
+        for (pk, row) in self.rows.iter() {
+            index.add(pk, row);
+        }
+        self.indexes.push(NamedIndex {
+            name: name.to_owned(),
+            index: ~index as ~SecondaryIndex<PK, Row>:Send+Share
+        });
+        true
+    }
+
+    fn find_index<'a>(&'a self, name: &str) -> Option<&'a ~SecondaryIndex<PK, Row>:Send+Share>
+    {
+        self.indexes.iter().find(|named| named.name.as_slice() == name).map(|named| &named.index)
+    }
+
+    // the map behind an index, None if there is no index called name or
+    // its keys are not of type IK
+    pub fn index<'a, IK: Default+Clone+TotalOrd+Send+Share+'static>(&'a self, name: &str) -> Option<&'a BTreeMap<IK, BTreeSet<PK>>>
+    {
+        match self.find_index(name) {
+            Some(index) => index.as_any().as_ref::<Index<PK, Row, IK>>().map(|index| &index.map),
+            None => None
+        }
+    }
+
+    // every row whose indexed key equals ik, in primary key order. None
+    // like index if there is no such index.
+    pub fn lookup<'a, IK: Default+Clone+TotalOrd+Send+Share+'static>(&'a self, name: &str, ik: &IK) -> Option<Vec<&'a Row>>
+    {
+        let index = match self.index::<IK>(name) {
+            Some(index) => index,
+            None => return None
+        };
+        match index.find(ik) {
+            Some(pks) => Some(pks.iter().map(|pk| self.rows.find(pk).unwrap()).collect()),
+            None => Some(Vec::new())
+        }
+    }
+
+    pub fn rows<'a>(&'a self) -> &'a BTreeMap<PK, Row>
+    {
+        &self.rows
+    }
+
+    pub fn get<'a>(&'a self, pk: &PK) -> Option<&'a Row>
+    {
+        self.rows.find(pk)
+    }
+
+    pub fn len(&self) -> uint
+    {
+        self.rows.len()
+    }
+
+    // insert or replace a row, returning the one it replaced
+    pub fn insert(&mut self, pk: PK, row: Row) -> Option<Row>
+    {
+        {
+            let old = self.rows.find(&pk);
+            for named in self.indexes.mut_iter() {
+                match old {
+                    Some(old) => named.index.remove(&pk, old),
+                    None => ()
+                }
+                named.index.add(&pk, &row);
+            }
+        }
+        self.rows.swap(pk, row)
+    }
+
+    // change a row in place, false if there is no row with that key
+    pub fn update(&mut self, pk: &PK, f: |&mut Row|) -> bool
+    {
+        let mut row = match self.rows.find(pk) {
+            Some(row) => row.clone(),
+            None => return false
+        };
+        f(&mut row);
+        self.insert(pk.clone(), row);
+        true
+    }
+
+    pub fn remove(&mut self, pk: &PK) -> Option<Row>
+    {
+        let row = self.rows.pop(pk);
+        match row {
+            Some(ref row) => {
+                for named in self.indexes.mut_iter() {
+                    named.index.remove(pk, row);
+                }
+            },
+            None => ()
+        }
+        row
+    }
+}
+
+impl<PK: Default+Clone+TotalOrd+Send+Share+'static, Row: Default+Clone+Send+Share+'static> Clone for Table<PK, Row>
+{
+    fn clone(&self) -> Table<PK, Row>
+    {
+        Table {
+            rows: self.rows.clone(),
+            indexes: self.indexes.iter().map(|named| {
+                NamedIndex {
+                    name: named.name.clone(),
+                    index: named.index.clone_index()
+                }
+            }).collect()
+        }
+    }
+}
@@ -1,8 +1,9 @@
 extern crate cow;
 extern crate rand;
+extern crate serialize;
 
 mod btree {
-    use cow::btree::{BTreeMap};
+    use cow::btree::{BTreeMap, Change, Added, Removed, Changed};
     use rand::{Rng, IsaacRng, SeedableRng};
     static NUM_TASKS: uint = 8;
 
@@ -687,11 +688,137 @@ mod btree {
     fn cow_tasks_find_mut_10_000() { cow_tasks_find_mut_n(10_000) }
     #[test]
     fn cow_tasks_find_mut_100_000() { cow_tasks_find_mut_n(100_000) }
+
+
+    #[test]
+    fn test_diff()
+    {
+        let mut old = BTreeMap::new();
+        for i in range(0, 10_000u) {
+            old.insert(i, i);
+        }
+
+        let mut new = old.clone();
+        new.insert(20_000, 1);
+        new.pop(&5_000);
+        new.insert(7_000, 0);
+        assert!(old.diff(&old.clone()).len() == 0);
+
+        let changes = old.diff(&new);
+        assert!(changes.len() == 3);
+        match *changes.get(0) {
+            Removed(5_000, 5_000) => (),
+            _ => fail!("expected 5000 to be removed")
+        }
+        match *changes.get(1) {
+            Changed(7_000, 7_000, 0) => (),
+            _ => fail!("expected 7000 to change")
+        }
+        match *changes.get(2) {
+            Added(20_000, 1) => (),
+            _ => fail!("expected 20000 to be added")
+        }
+
+        // unrelated maps are compared entry by entry
+        let mut other = BTreeMap::new();
+        for i in range(5_000, 15_000u) {
+            other.insert(i, i);
+        }
+        let changes = old.diff(&other);
+        assert!(changes.len() == 10_000);
+        assert!(changes.iter().filter(|c| match **c { Added(..) => true, _ => false }).count() == 5_000);
+    }
+
+    // (kind, key, old value, new value) for every change, 0 removed, 1 added, 2 changed
+    fn flatten(changes: &Vec<Change<uint, uint>>) -> Vec<(uint, uint, uint, uint)>
+    {
+        changes.iter().map(|c| match *c {
+            Removed(k, v) => (0, k, v, 0),
+            Added(k, v) => (1, k, 0, v),
+            Changed(k, a, b) => (2, k, a, b)
+        }).collect()
+    }
+
+    // looks up every key of each map in the other
+    fn naive_diff(old: &BTreeMap<uint, uint>, new: &BTreeMap<uint, uint>) -> Vec<(uint, uint, uint, uint)>
+    {
+        let mut changes = BTreeMap::new();
+        for (k, a) in old.iter() {
+            match new.find(k) {
+                None => { changes.insert(*k, (0, *a, 0)); },
+                Some(b) => if a != b { changes.insert(*k, (2, *a, *b)); }
+            }
+        }
+        for (k, b) in new.iter() {
+            if old.find(k).is_none() {
+                changes.insert(*k, (1, 0, *b));
+            }
+        }
+        changes.iter().map(|(k, &(kind, a, b))| (kind, *k, a, b)).collect()
+    }
+
+    fn diff_structural_n(len: uint)
+    {
+        let mut old = BTreeMap::new();
+        for i in range(0, len) {
+            old.insert(i * 2, i);
+        }
+
+        // a run of new keys splits the leaves and internal nodes around it
+        let mut new = old.clone();
+        for i in range(0, len / 4) {
+            new.insert(len / 2 + i * 2 + 1, i);
+        }
+        assert!(flatten(&old.diff(&new)) == naive_diff(&old, &new));
+
+        // popping the largest keys changes the keys of every node on the
+        // right edge
+        let mut new = old.clone();
+        for i in range(len - len / 10, len) {
+            new.pop(&(i * 2));
+        }
+        assert!(flatten(&old.diff(&new)) == naive_diff(&old, &new));
+
+        // removing a block merges the nodes it leaves underfull, with a
+        // few scattered changes on top
+        let mut new = old.clone();
+        for i in range(len / 3, len / 2) {
+            new.pop(&(i * 2));
+        }
+        let mut rng = IsaacRng::new().unwrap();
+        rng.reseed([60388u32]);
+        for _ in range(0, 10) {
+            let k = rng.gen_range(0, len) * 2;
+            new.insert(k, len + k);
+        }
+        new.insert(len * 2, 0);
+        assert!(flatten(&old.diff(&new)) == naive_diff(&old, &new));
+        assert!(flatten(&new.diff(&old)) == naive_diff(&new, &old));
+    }
+
+    #[test]
+    fn diff_structural_10() { diff_structural_n(10) }
+    #[test]
+    fn diff_structural_1_000() { diff_structural_n(1_000) }
+    #[test]
+    fn diff_structural_100_000() { diff_structural_n(100_000) }
+
 }
 
 mod join {
     use cow::btree::{BTreeMap, BTreeSet};
     use cow::join::{join_maps, join_sets, join_set_to_map};
+    use cow::join::{left_join_maps, right_join_maps, full_outer_join_maps};
+    use cow::join::{semi_join_maps, anti_join_maps, anti_join_sets, anti_join_map_set};
+    use cow::join::{seek_join_maps, seek_join_sets, seek_join_set_to_map};
+    use cow::join::{join_sets_n, join_maps_n, linear_seek_set, linear_seek_map};
+    use cow::join::{linear_join_sets_n, linear_join_maps_n};
+    use cow::join::{join_maps_multi, strict_join_maps_multi, JoinError, Unsorted};
+    use cow::join::{join_sets_multi, strict_join_sets_multi};
+    use cow::join::{checked_sorted, checked_sorted_map, checked_join_maps, checked_join_sets, checked_join_set_to_map};
+    use cow::join::join_by;
+    use cow::join::{join_maps_range, join_sets_range, join_set_to_map_range};
+    use cow::join::{group_by_key, count_by_key, sum_by_key, min_by_key, max_by_key, fold_by_key};
 
     #[test]
     fn test_map_join_10_shared_set()
@@ -899,4 +1026,2196 @@ mod join {
         }
 
     }
-}
\ No newline at end of file
+
+    fn outer_maps() -> (BTreeMap<uint, uint>, BTreeMap<uint, uint>)
+    {
+        let mut a = BTreeMap::new();
+        let mut b = BTreeMap::new();
+
+        for i in range(0, 100u) {
+            if i % 2 == 0 {
+                a.insert(i, i);
+            }
+            if i % 3 == 0 {
+                b.insert(i, i * 10);
+            }
+        }
+        (a, b)
+    }
+
+    #[test]
+    fn test_left_join()
+    {
+        let (a, b) = outer_maps();
+        let mut expected = range(0, 100u).filter(|i| i % 2 == 0);
+
+        for (&k, (data_a, data_b)) in left_join_maps(a.iter(), b.iter()) {
+            assert!(k == expected.next().unwrap());
+            assert!(*data_a.unwrap() == k);
+            match data_b {
+                Some(&v) => assert!(k % 3 == 0 && v == k * 10),
+                None => assert!(k % 3 != 0)
+            }
+        }
+        assert!(expected.next().is_none());
+    }
+
+    #[test]
+    fn test_right_join()
+    {
+        let (a, b) = outer_maps();
+        let mut expected = range(0, 100u).filter(|i| i % 3 == 0);
+
+        for (&k, (data_a, data_b)) in right_join_maps(a.iter(), b.iter()) {
+            assert!(k == expected.next().unwrap());
+            assert!(*data_b.unwrap() == k * 10);
+            match data_a {
+                Some(&v) => assert!(k % 2 == 0 && v == k),
+                None => assert!(k % 2 != 0)
+            }
+        }
+        assert!(expected.next().is_none());
+    }
+
+    #[test]
+    fn test_full_outer_join()
+    {
+        let (a, b) = outer_maps();
+        let mut expected = range(0, 100u).filter(|i| i % 2 == 0 || i % 3 == 0);
+
+        for (&k, (data_a, data_b)) in full_outer_join_maps(a.iter(), b.iter()) {
+            assert!(k == expected.next().unwrap());
+            assert!(data_a.is_some() == (k % 2 == 0));
+            assert!(data_b.is_some() == (k % 3 == 0));
+        }
+        assert!(expected.next().is_none());
+    }
+
+    #[test]
+    fn test_full_outer_join_empty()
+    {
+        let (a, _) = outer_maps();
+        let b: BTreeMap<uint, uint> = BTreeMap::new();
+
+        assert!(full_outer_join_maps(a.iter(), b.iter()).count() == a.len());
+        assert!(full_outer_join_maps(b.iter(), a.iter()).count() == a.len());
+        assert!(right_join_maps(a.iter(), b.iter()).count() == 0);
+        assert!(left_join_maps(b.iter(), a.iter()).count() == 0);
+    }
+
+    #[test]
+    fn test_semi_join()
+    {
+        let mut users = BTreeMap::new();
+        let mut orders = BTreeMap::new();
+
+        for i in range(0, 100u) {
+            users.insert(i, i);
+            if i % 4 == 0 {
+                orders.insert(i, i * 2);
+            }
+        }
+
+        let mut expected = range(0, 100u).filter(|i| i % 4 == 0);
+        for (&k, &v) in semi_join_maps(users.iter(), orders.iter()) {
+            assert!(k == expected.next().unwrap());
+            assert!(v == k);
+        }
+        assert!(expected.next().is_none());
+    }
+
+    #[test]
+    fn test_anti_join_maps()
+    {
+        let mut users = BTreeMap::new();
+        let mut orders = BTreeMap::new();
+
+        for i in range(0, 100u) {
+            users.insert(i, i);
+            if i % 4 == 0 {
+                orders.insert(i, i * 2);
+            }
+        }
+        orders.insert(1000, 0);
+
+        let mut expected = range(0, 100u).filter(|i| i % 4 != 0);
+        for (&k, &v) in anti_join_maps(users.iter(), orders.iter()) {
+            assert!(k == expected.next().unwrap());
+            assert!(v == k);
+        }
+        assert!(expected.next().is_none());
+    }
+
+    #[test]
+    fn test_anti_join_sets()
+    {
+        let mut a = BTreeSet::new();
+        let mut b = BTreeSet::new();
+
+        for i in range(0, 100u) {
+            a.insert(i);
+            if i % 3 == 0 {
+                b.insert(i);
+            }
+        }
+
+        let mut expected = range(0, 100u).filter(|i| i % 3 != 0);
+        for &k in anti_join_sets(a.iter(), b.iter()) {
+            assert!(k == expected.next().unwrap());
+        }
+        assert!(expected.next().is_none());
+
+        assert!(anti_join_sets(b.iter(), a.iter()).count() == 0);
+    }
+
+    #[test]
+    fn test_anti_join_map_set()
+    {
+        let mut a = BTreeMap::new();
+        let mut b = BTreeSet::new();
+
+        for i in range(0, 100u) {
+            a.insert(i, i);
+            if i >= 50 {
+                b.insert(i);
+            }
+        }
+
+        let mut expected = range(0, 50u);
+        for (&k, _) in anti_join_map_set(a.iter(), b.iter()) {
+            assert!(k == expected.next().unwrap());
+        }
+        assert!(expected.next().is_none());
+    }
+
+    #[test]
+    fn test_seek()
+    {
+        let mut a = BTreeMap::new();
+        for i in range(0, 10_000u) {
+            a.insert(i * 2, i);
+        }
+
+        let mut iter = a.iter();
+        iter.seek(&101);
+        assert!(iter.next() == Some((&102, &51)));
+        iter.seek(&102);
+        assert!(iter.next() == Some((&104, &52)));
+        // seeking backwards does nothing
+        iter.seek(&0);
+        assert!(iter.next() == Some((&106, &53)));
+        iter.seek(&106);
+        assert!(iter.next() == Some((&108, &54)));
+        iter.seek(&15_000);
+        assert!(iter.next() == Some((&15_000, &7_500)));
+        assert!(iter.count() == 10_000 - 7_501);
+
+        let mut iter = a.iter();
+        iter.seek(&20_000);
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn test_seek_set()
+    {
+        let mut a = BTreeSet::new();
+        for i in range(0, 10_000u) {
+            a.insert(i * 3);
+        }
+
+        for i in range(0, 30_000u) {
+            let mut iter = a.iter();
+            iter.seek(&i);
+            let expected = (i + 2) / 3 * 3;
+            match iter.next() {
+                Some(&v) => assert!(v == expected),
+                None => assert!(expected >= 30_000)
+            }
+        }
+    }
+
+    #[test]
+    fn test_seek_join_small_large()
+    {
+        let mut small = BTreeMap::new();
+        let mut large = BTreeMap::new();
+
+        for i in range(0, 10u) {
+            small.insert(i * 9_999, i);
+        }
+        for i in range(0, 100_000u) {
+            large.insert(i, i);
+        }
+
+        let mut expected = range(0, 10u).map(|i| i * 9_999);
+        for (&k, (&data_a, &data_b)) in seek_join_maps(small.iter(), large.iter()) {
+            assert!(k == expected.next().unwrap());
+            assert!(data_a * 9_999 == k);
+            assert!(data_b == k);
+        }
+        assert!(expected.next().is_none());
+
+        assert!(seek_join_maps(large.iter(), small.iter()).count() == 10);
+    }
+
+    #[test]
+    fn test_seek_join_matches_join()
+    {
+        let mut a = BTreeSet::new();
+        let mut b = BTreeSet::new();
+        let mut c = BTreeMap::new();
+
+        for i in range(0, 10_000u) {
+            if i % 3 == 0 {
+                a.insert(i);
+            }
+            if i % 7 == 0 {
+                b.insert(i);
+            }
+            if i % 5 == 0 {
+                c.insert(i, i);
+            }
+        }
+
+        let slow: Vec<&uint> = join_sets(a.iter(), b.iter()).collect();
+        let fast: Vec<&uint> = seek_join_sets(a.iter(), b.iter()).collect();
+        assert!(slow == fast);
+
+        let slow: Vec<(&uint, &uint)> = join_set_to_map(a.iter(), c.iter()).collect();
+        let fast: Vec<(&uint, &uint)> = seek_join_set_to_map(a.iter(), c.iter()).collect();
+        assert!(slow == fast);
+    }
+
+    fn n_sets(n: uint, len: uint) -> Vec<BTreeSet<uint>>
+    {
+        range(0, n).map(|j| {
+            let mut set = BTreeSet::new();
+            for i in range(0, len) {
+                if i % (j + 2) == 0 {
+                    set.insert(i);
+                }
+            }
+            set
+        }).collect()
+    }
+
+    fn expected_n(n: uint, len: uint) -> Vec<uint>
+    {
+        range(0, len).filter(|i| range(0, n).all(|j| i % (j + 2) == 0)).collect()
+    }
+
+    fn join_sets_n_n(n: uint, len: uint)
+    {
+        let sets = n_sets(n, len);
+        let expected = expected_n(n, len);
+
+        let found: Vec<uint> = join_sets_n(sets.iter().map(|s| s.iter()).collect()).map(|&k| k).collect();
+        assert!(found == expected);
+
+        let plain: Vec<uint> = join_sets_n(sets.iter().map(|s| linear_seek_set(s.iter().map(|&k| k))).collect()).collect();
+        assert!(plain == expected);
+
+        let vecs: Vec<Vec<uint>> = sets.iter().map(|s| s.iter().map(|&k| k).collect()).collect();
+        let plain: Vec<uint> = linear_join_sets_n(vecs.move_iter().map(|v| v.move_iter()).collect()).collect();
+        assert!(plain == expected);
+    }
+
+    fn join_maps_n_n(n: uint, len: uint)
+    {
+        let maps: Vec<BTreeMap<uint, uint>> = n_sets(n, len).iter().enumerate().map(|(j, s)| {
+            let mut map = BTreeMap::new();
+            for &k in s.iter() {
+                map.insert(k, j);
+            }
+            map
+        }).collect();
+        let expected = expected_n(n, len);
+
+        let mut count = 0;
+        for (&k, data) in join_maps_n(maps.iter().map(|m| m.iter()).collect()) {
+            assert!(k == *expected.get(count));
+            let data: Vec<uint> = data.move_iter().map(|&d| d).collect();
+            assert!(data == range(0, n).collect());
+            count += 1;
+        }
+        assert!(count == expected.len());
+
+        let plain = join_maps_n(maps.iter().map(|m| linear_seek_map(m.iter())).collect());
+        assert!(plain.count() == expected.len());
+
+        let plain = linear_join_maps_n(maps.iter().map(|m| m.iter().map(|(&k, &d)| (k, d))).collect());
+        assert!(plain.map(|(k, _)| k).collect::<Vec<uint>>() == expected);
+    }
+
+    #[test]
+    fn test_join_sets_n_1() { join_sets_n_n(1, 1000) }
+    #[test]
+    fn test_join_sets_n_3() { join_sets_n_n(3, 10_000) }
+    #[test]
+    fn test_join_sets_n_8() { join_sets_n_n(8, 100_000) }
+
+    #[test]
+    fn test_join_maps_n_1() { join_maps_n_n(1, 1000) }
+    #[test]
+    fn test_join_maps_n_3() { join_maps_n_n(3, 10_000) }
+    #[test]
+    fn test_join_maps_n_8() { join_maps_n_n(8, 100_000) }
+
+    #[test]
+    fn test_join_sets_n_empty()
+    {
+        let sets: Vec<BTreeSet<uint>> = Vec::new();
+        assert!(join_sets_n(sets.iter().map(|s| s.iter()).collect()).next().is_none());
+    }
+
+    fn algebra_sets(len: uint) -> (BTreeSet<uint>, BTreeSet<uint>)
+    {
+        let mut a = BTreeSet::new();
+        let mut b = BTreeSet::new();
+
+        for i in range(0, len) {
+            if i % 2 == 0 {
+                a.insert(i);
+            }
+            if i % 3 == 0 {
+                b.insert(i);
+            }
+        }
+        (a, b)
+    }
+
+    fn set_algebra_n(len: uint)
+    {
+        let (a, b) = algebra_sets(len);
+
+        let found: Vec<uint> = a.union(&b).map(|&k| k).collect();
+        assert!(found == range(0, len).filter(|i| i % 2 == 0 || i % 3 == 0).collect());
+
+        let found: Vec<uint> = a.intersection(&b).map(|&k| k).collect();
+        assert!(found == range(0, len).filter(|i| i % 6 == 0).collect());
+
+        let found: Vec<uint> = a.difference(&b).map(|&k| k).collect();
+        assert!(found == range(0, len).filter(|i| i % 2 == 0 && i % 3 != 0).collect());
+
+        let found: Vec<uint> = a.symmetric_difference(&b).map(|&k| k).collect();
+        assert!(found == range(0, len).filter(|i| (i % 2 == 0) != (i % 3 == 0)).collect());
+    }
+
+    #[test]
+    fn test_set_algebra_10() { set_algebra_n(10) }
+    #[test]
+    fn test_set_algebra_990() { set_algebra_n(990) }
+    #[test]
+    fn test_set_algebra_10_000() { set_algebra_n(10_000) }
+
+    #[test]
+    fn test_set_predicates()
+    {
+        let (a, b) = algebra_sets(1000);
+        let mut c = BTreeSet::new();
+        let mut d = BTreeSet::new();
+        for i in range(0, 1000u) {
+            if i % 4 == 0 {
+                c.insert(i);
+            }
+            if i % 2 == 1 {
+                d.insert(i);
+            }
+        }
+
+        assert!(c.is_subset(&a));
+        assert!(!a.is_subset(&c));
+        assert!(a.is_superset(&c));
+        assert!(!b.is_subset(&a));
+        assert!(a.is_disjoint(&d));
+        assert!(!b.is_disjoint(&d));
+        assert!(BTreeSet::new().is_subset(&a));
+        assert!(BTreeSet::new().is_disjoint(&a));
+    }
+
+    #[test]
+    fn test_join_multi()
+    {
+        let a = vec!((1u, 'a'), (1, 'b'), (2, 'c'), (4, 'd'), (4, 'e'), (5, 'f'));
+        let b = vec!((1u, 10u), (3, 30), (4, 40), (4, 41), (4, 42), (6, 60));
+
+        let found: Vec<(uint, (char, uint))> = join_maps_multi(a.move_iter(), b.move_iter()).collect();
+        assert!(found == vec!((1, ('a', 10)), (1, ('b', 10)),
+                              (4, ('d', 40)), (4, ('d', 41)), (4, ('d', 42)),
+                              (4, ('e', 40)), (4, ('e', 41)), (4, ('e', 42))));
+    }
+
+    #[test]
+    fn test_join_multi_unique()
+    {
+        let mut a = BTreeMap::new();
+        let mut b = BTreeMap::new();
+
+        for i in range(0, 1000u) {
+            if i % 2 == 0 {
+                a.insert(i, i);
+            }
+            if i % 3 == 0 {
+                b.insert(i, i);
+            }
+        }
+
+        let slow: Vec<(&uint, (&uint, &uint))> = join_maps(a.iter(), b.iter()).collect();
+        let multi: Vec<(&uint, (&uint, &uint))> = join_maps_multi(a.iter(), b.iter()).collect();
+        assert!(slow == multi);
+    }
+
+    #[test]
+    fn test_join_multi_strict()
+    {
+        let a = vec!((1u, 1u), (1, 2), (3, 3), (2, 4), (5, 5));
+        let b = vec!((1u, 1u), (2, 2), (3, 3), (5, 5));
+
+        let mut iter = strict_join_maps_multi(a.move_iter(), b.move_iter());
+        assert!(iter.next().unwrap().is_ok());
+        assert!(iter.next().unwrap().is_ok());
+        assert!(iter.next().unwrap().is_ok());
+        match iter.next() {
+            Some(Err(Unsorted {position: 3})) => (),
+            _ => fail!("expected the unsorted item to be reported")
+        }
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn test_join_sets_multi()
+    {
+        let a = vec!(1u, 1, 2, 4, 4, 5);
+        let b = vec!(1u, 3, 4, 4, 4, 6);
+
+        let found: Vec<uint> = join_sets_multi(a.move_iter(), b.move_iter()).collect();
+        assert!(found == vec!(1, 1, 4, 4, 4, 4, 4, 4));
+
+        let a = vec!(1u, 3, 2, 5);
+        let b = vec!(1u, 2, 3, 5);
+        let found: Vec<Result<uint, JoinError>> = strict_join_sets_multi(a.move_iter(), b.move_iter()).collect();
+        assert!(found.len() == 3);
+        match (found.get(0), found.get(1)) {
+            (&Ok(1), &Ok(3)) => (),
+            _ => fail!("expected 1 and 3 before the error")
+        }
+        match *found.get(2) {
+            Err(Unsorted {position: 2}) => (),
+            _ => fail!("expected position 2 to be reported")
+        }
+    }
+
+    #[test]
+    fn test_checked_sorted()
+    {
+        let found: Vec<Result<uint, JoinError>> = checked_sorted(vec!(1u, 2, 2, 5, 3, 7).move_iter()).collect();
+        assert!(found.len() == 5);
+        assert!(found.iter().take(4).all(|r| r.is_ok()));
+        match *found.get(4) {
+            Err(Unsorted {position: 4}) => (),
+            _ => fail!("expected position 4 to be reported")
+        }
+
+        let mut map = BTreeMap::new();
+        for i in range(0, 1000u) {
+            map.insert(i, i);
+        }
+        assert!(checked_sorted_map(map.iter()).all(|r| r.is_ok()));
+    }
+
+    #[test]
+    fn test_checked_join_maps()
+    {
+        let mut a = BTreeMap::new();
+        for i in range(0, 100u) {
+            a.insert(i, i);
+        }
+        let b = vec!((&10u, 0u), (&20, 1), (&15, 2), (&30, 3));
+
+        let found: Vec<Result<(&uint, (&uint, uint)), JoinError>> = checked_join_maps(a.iter(), b.move_iter()).collect();
+        assert!(found.len() == 3);
+        assert!(found.get(0).is_ok() && found.get(1).is_ok());
+        match *found.get(2) {
+            Err(Unsorted {position: 2}) => (),
+            _ => fail!("expected position 2 to be reported")
+        }
+    }
+
+    #[test]
+    fn test_checked_join_sorted()
+    {
+        let mut a = BTreeSet::new();
+        let mut b = BTreeSet::new();
+        let mut c = BTreeMap::new();
+        for i in range(0, 1000u) {
+            a.insert(i);
+            if i % 2 == 0 {
+                b.insert(i);
+                c.insert(i, i);
+            }
+        }
+
+        assert!(checked_join_sets(a.iter(), b.iter()).all(|r| r.is_ok()));
+        assert!(checked_join_sets(a.iter(), b.iter()).count() == 500);
+        assert!(checked_join_set_to_map(a.iter(), c.iter()).all(|r| r.is_ok()));
+        assert!(checked_join_set_to_map(a.iter(), c.iter()).count() == 500);
+    }
+
+    #[test]
+    fn test_checked_join_sets_unsorted()
+    {
+        let a = vec!(1u, 2, 3, 4, 5);
+        let b = vec!(1u, 4, 2, 5);
+
+        let found: Vec<Result<uint, JoinError>> = checked_join_sets(a.move_iter(), b.move_iter()).collect();
+        assert!(found.len() == 3);
+        assert!(found.get(0).is_ok() && found.get(1).is_ok());
+        assert!(found.get(2).is_err());
+    }
+
+    #[test]
+    fn test_checked_join_unsorted_tail()
+    {
+        // b runs out before the misordered entry of a is reached
+        let found: Vec<Result<uint, JoinError>> = checked_join_sets(vec!(5u, 1).move_iter(), vec!(1u).move_iter()).collect();
+        assert!(found.len() == 1);
+        match *found.get(0) {
+            Err(Unsorted {position: 1}) => (),
+            _ => fail!("expected position 1 to be reported")
+        }
+
+        let a = vec!((1u, 0u), (3, 0), (2, 0));
+        let b = vec!((1u, 1u));
+        let found: Vec<Result<(uint, (uint, uint)), JoinError>> = checked_join_maps(a.move_iter(), b.move_iter()).collect();
+        assert!(found.len() == 2);
+        assert!(found.get(0).is_ok() && found.get(1).is_err());
+
+        let found: Vec<Result<(uint, uint), JoinError>> = checked_join_set_to_map(vec!(1u).move_iter(), vec!((4u, 0u), (2, 0)).move_iter()).collect();
+        assert!(found.len() == 1 && found.get(0).is_err());
+    }
+
+    struct Person {id: uint, name: ~str}
+    struct Order {person: uint, total: uint}
+
+    #[test]
+    fn test_join_by()
+    {
+        let people = vec!(Person {id: 1, name: ~"ann"}, Person {id: 2, name: ~"bob"}, Person {id: 4, name: ~"eve"});
+        let orders = vec!(Order {person: 2, total: 10}, Order {person: 3, total: 20}, Order {person: 4, total: 30});
+
+        let found: Vec<(&Person, &Order)> = join_by(people.iter(), orders.iter(), |p| p.id, |o| o.person).collect();
+        assert!(found.len() == 2);
+        let &(p, o) = found.get(0);
+        assert!(p.name == ~"bob" && o.total == 10);
+        let &(p, o) = found.get(1);
+        assert!(p.name == ~"eve" && o.total == 30);
+    }
+
+    #[test]
+    fn test_join_by_borrowed_key()
+    {
+        let a = vec!((~"a", 1u), (~"c", 2), (~"d", 3));
+        let b = vec!((4u, ~"b"), (5, ~"c"), (6, ~"d"));
+
+        let found: Vec<uint> = join_by(a.iter(), b.iter(),
+                                        |&r| { let &(ref k, _) = r; k.as_slice() },
+                                        |&r| { let &(_, ref k) = r; k.as_slice() })
+            .map(|(&(_, x), &(y, _))| x + y).collect();
+        assert!(found == vec!(7u, 9));
+    }
+
+    fn join_by_n(len: uint)
+    {
+        let a: Vec<(uint, uint)> = range(0, len).map(|i| (i, i * 2)).collect();
+        let b: Vec<uint> = range(0, len).filter(|i| i % 3 == 0).collect();
+
+        let mut count = 0;
+        for (&(i, d), &k) in join_by(a.iter(), b.iter(), |&&(i, _)| i, |&&k| k) {
+            assert!(i == k && d == i * 2);
+            count += 1;
+        }
+        assert!(count == b.len());
+    }
+
+    #[test]
+    fn join_by_10() { join_by_n(10) }
+    #[test]
+    fn join_by_1_000() { join_by_n(1_000) }
+    #[test]
+    fn join_by_100_000() { join_by_n(100_000) }
+
+    #[test]
+    fn test_range()
+    {
+        let mut map = BTreeMap::new();
+        for i in range(0, 10_000u) {
+            map.insert(i * 2, i);
+        }
+
+        assert!(map.rank(&0) == 0);
+        assert!(map.rank(&3) == 2);
+        assert!(map.rank(&4) == 2);
+        assert!(map.rank(&100_000) == 10_000);
+
+        let found: Vec<uint> = map.range(Some(&101), Some(&111)).map(|(&k, _)| k).collect();
+        assert!(found == vec!(102u, 104, 106, 108, 110));
+        assert!(map.range(None, Some(&10)).count() == 5);
+        assert!(map.range(Some(&19_990), None).count() == 5);
+        assert!(map.range(Some(&50), Some(&50)).count() == 0);
+        assert!(map.range(Some(&60), Some(&50)).count() == 0);
+
+        // seeking past the limit ends the iteration
+        let mut iter = map.range(None, Some(&1_000));
+        iter.seek(&5_000);
+        assert!(iter.next().is_none());
+
+        let mut set = BTreeSet::new();
+        for i in range(0, 1000u) {
+            set.insert(i);
+        }
+        let found: Vec<&uint> = set.range(Some(&10), Some(&13)).collect();
+        assert!(found == vec!(&10u, &11, &12));
+    }
+
+    fn join_maps_range_n(len: uint)
+    {
+        let mut a = BTreeMap::new();
+        let mut b = BTreeMap::new();
+        for i in range(0, len) {
+            a.insert(i, i);
+            if i % 3 == 0 {
+                b.insert(i, i * 2);
+            }
+        }
+        let (lo, hi) = (len / 4, len / 2);
+
+        let filtered: Vec<(&uint, (&uint, &uint))> = join_maps(a.iter(), b.iter())
+            .filter(|&(&k, _)| k >= lo && k < hi).collect();
+        let ranged: Vec<(&uint, (&uint, &uint))> = join_maps_range(a.iter(), b.iter(), Some(&lo), Some(&hi)).collect();
+        assert!(filtered == ranged);
+
+        let filtered: Vec<&uint> = join_sets(a.iter().map(|(k, _)| k), b.iter().map(|(k, _)| k))
+            .filter(|&&k| k >= lo).collect();
+        let ranged: Vec<&uint> = join_sets_range(linear_seek_set(a.iter().map(|(k, _)| k)),
+                                                 linear_seek_set(b.iter().map(|(k, _)| k)),
+                                                 Some(&lo), None).collect();
+        assert!(filtered == ranged);
+    }
+
+    #[test]
+    fn join_maps_range_10() { join_maps_range_n(10) }
+    #[test]
+    fn join_maps_range_1_000() { join_maps_range_n(1_000) }
+    #[test]
+    fn join_maps_range_100_000() { join_maps_range_n(100_000) }
+
+    #[test]
+    fn test_join_set_to_map_range()
+    {
+        let mut set = BTreeSet::new();
+        let mut map = BTreeMap::new();
+        for i in range(0, 1000u) {
+            set.insert(i * 5);
+            map.insert(i * 3, i);
+        }
+
+        let found: Vec<&uint> = join_set_to_map_range(set.iter(), map.iter(), Some(&100), Some(&200))
+            .map(|(k, _)| k).collect();
+        assert!(found == vec!(&105u, &120, &135, &150, &165, &180, &195));
+    }
+
+    #[test]
+    fn test_group_by_key()
+    {
+        let data = vec!((1u, 10u), (1, 11), (2, 20), (4, 40), (4, 41), (4, 42));
+
+        let groups: Vec<(uint, Vec<uint>)> = group_by_key(data.clone().move_iter()).collect();
+        assert!(groups == vec!((1u, vec!(10u, 11)), (2, vec!(20)), (4, vec!(40, 41, 42))));
+
+        let counts: Vec<(uint, uint)> = count_by_key(data.clone().move_iter()).collect();
+        assert!(counts == vec!((1u, 2u), (2, 1), (4, 3)));
+
+        let sums: Vec<(uint, uint)> = sum_by_key(data.clone().move_iter()).collect();
+        assert!(sums == vec!((1u, 21u), (2, 20), (4, 123)));
+
+        let mins: Vec<(uint, uint)> = min_by_key(data.clone().move_iter()).collect();
+        assert!(mins == vec!((1u, 10u), (2, 20), (4, 40)));
+
+        let maxs: Vec<(uint, uint)> = max_by_key(data.clone().move_iter()).collect();
+        assert!(maxs == vec!((1u, 11u), (2, 20), (4, 42)));
+
+        let folded: Vec<(uint, uint)> = fold_by_key(data.clone().move_iter(), 1u, |acc, d| acc * (d % 10 + 1)).collect();
+        assert!(folded == vec!((1u, 2u), (2, 1), (4, 6)));
+
+        assert!(group_by_key(Vec::<(uint, uint)>::new().move_iter()).next().is_none());
+    }
+
+    fn aggregate_join_n(len: uint)
+    {
+        let mut a = BTreeMap::new();
+        let mut b = BTreeMap::new();
+        for i in range(0, len) {
+            a.insert(i, i);
+            b.insert(i, 1u);
+        }
+
+        // group the join output on key / 10
+        let sums: Vec<(uint, uint)> = sum_by_key(join_maps(a.iter(), b.iter()).map(|(&k, (&x, &y))| (k / 10, x * y))).collect();
+        assert!(sums.len() == (len + 9) / 10);
+        for &(g, sum) in sums.iter() {
+            let expected = range(g * 10, ::std::cmp::min(g * 10 + 10, len)).fold(0, |s, i| s + i);
+            assert!(sum == expected);
+        }
+
+        let counts: Vec<(uint, uint)> = count_by_key(join_maps(a.iter(), b.iter()).map(|(&k, d)| (k / 10, d))).collect();
+        assert!(counts.iter().fold(0, |s, &(_, c)| s + c) == len);
+    }
+
+    #[test]
+    fn aggregate_join_10() { aggregate_join_n(10) }
+    #[test]
+    fn aggregate_join_1_000() { aggregate_join_n(1_000) }
+    #[test]
+    fn aggregate_join_100_000() { aggregate_join_n(100_000) }
+
+}
+
+mod reconcile {
+    use cow::btree::SummaryMap;
+    use cow::reconcile::{SyncSession, Fingerprint, loopback};
+
+    type Replica = SummaryMap<uint, uint, Fingerprint>;
+
+    fn run(a: &Replica, b: &Replica) -> (Vec<(uint, uint)>, Vec<(uint, uint)>)
+    {
+        let (mut ta, mut tb) = loopback();
+        let mut sa = SyncSession::new(a);
+        let mut sb = SyncSession::new(b);
+
+        sa.start(&mut ta);
+        loop {
+            let pa = sa.poll(&mut ta);
+            let pb = sb.poll(&mut tb);
+            if !pa && !pb {
+                break;
+            }
+        }
+
+        assert!(sa.is_done() && sb.is_done());
+        (sa.unwrap(), sb.unwrap())
+    }
+
+    fn missing_n(len: uint)
+    {
+        let mut a: Replica = SummaryMap::new();
+        let mut b: Replica = SummaryMap::new();
+
+        for i in range(0, len) {
+            a.insert(i, i);
+            if i % 7 != 0 {
+                b.insert(i, i);
+            }
+        }
+        b.insert(len, len);
+
+        let (from_a, from_b) = run(&a, &b);
+
+        let expected: Vec<(uint, uint)> = range(0, len).filter(|i| i % 7 == 0).map(|i| (i, i)).collect();
+        assert!(from_a == expected);
+        assert!(from_b == vec!((len, len)));
+    }
+
+    fn changed_n(len: uint)
+    {
+        let mut a: Replica = SummaryMap::new();
+        for i in range(0, len) {
+            a.insert(i, i);
+        }
+
+        let mut b = a.clone();
+        b.insert(len / 2, 0);
+
+        let (from_a, from_b) = run(&a, &b);
+        assert!(from_a == vec!((len / 2, len / 2)));
+        assert!(from_b == vec!((len / 2, 0)));
+    }
+
+    #[test]
+    fn identical()
+    {
+        let mut a: Replica = SummaryMap::new();
+        for i in range(0, 1000u) {
+            a.insert(i, i);
+        }
+        let b = a.clone();
+
+        let (from_a, from_b) = run(&a, &b);
+        assert!(from_a.is_empty());
+        assert!(from_b.is_empty());
+    }
+
+    #[test]
+    fn one_empty()
+    {
+        let mut a: Replica = SummaryMap::new();
+        let b: Replica = SummaryMap::new();
+        for i in range(0, 1000u) {
+            a.insert(i, i);
+        }
+
+        let (from_a, from_b) = run(&a, &b);
+        assert!(from_a.len() == 1000);
+        assert!(from_b.is_empty());
+    }
+
+    #[test]
+    fn missing_10() { missing_n(10) }
+    #[test]
+    fn missing_990() { missing_n(990) }
+    #[test]
+    fn missing_10_000() { missing_n(10_000) }
+
+    #[test]
+    fn changed_10() { changed_n(10) }
+    #[test]
+    fn changed_990() { changed_n(990) }
+    #[test]
+    fn changed_10_000() { changed_n(10_000) }
+    #[test]
+    fn changed_100_000() { changed_n(100_000) }
+}
+
+mod serial {
+    use cow::btree::{BTreeMap, BTreeSet};
+    use cow::serial::{decode_set, RejectDuplicates, KeepFirst, DuplicateKey};
+    use serialize::{Decodable, json};
+    use std::str;
+
+    fn roundtrip_map_n(len: uint)
+    {
+        let mut map: BTreeMap<~str, uint> = BTreeMap::new();
+        for i in range(0, len) {
+            map.insert(format!("{:08u}", i), i);
+        }
+
+        let encoded = str::from_utf8_owned(json::Encoder::buffer_encode(&map)).unwrap();
+        let mut decoder = json::Decoder::new(json::from_str(encoded).unwrap());
+        let decoded: BTreeMap<~str, uint> = Decodable::decode(&mut decoder).unwrap();
+
+        assert!(decoded.len() == len);
+        for (a, b) in map.iter().zip(decoded.iter()) {
+            assert!(a == b);
+        }
+    }
+
+    fn roundtrip_set_n(len: uint)
+    {
+        let mut set = BTreeSet::new();
+        for i in range(0, len) {
+            set.insert(i);
+        }
+
+        let encoded = str::from_utf8_owned(json::Encoder::buffer_encode(&set)).unwrap();
+        let mut decoder = json::Decoder::new(json::from_str(encoded).unwrap());
+        let decoded: BTreeSet<uint> = Decodable::decode(&mut decoder).unwrap();
+
+        assert!(decoded.len() == len);
+        for (a, b) in set.iter().zip(decoded.iter()) {
+            assert!(a == b);
+        }
+    }
+
+    #[test]
+    fn roundtrip_map_10() { roundtrip_map_n(10) }
+    #[test]
+    fn roundtrip_map_990() { roundtrip_map_n(990) }
+    #[test]
+    fn roundtrip_map_10_000() { roundtrip_map_n(10_000) }
+
+    #[test]
+    fn roundtrip_set_10() { roundtrip_set_n(10) }
+    #[test]
+    fn roundtrip_set_990() { roundtrip_set_n(990) }
+    #[test]
+    fn roundtrip_set_10_000() { roundtrip_set_n(10_000) }
+
+    #[test]
+    fn unsorted_set()
+    {
+        let mut decoder = json::Decoder::new(json::from_str("[9, 3, 7, 1]").unwrap());
+        let set: BTreeSet<uint> = Decodable::decode(&mut decoder).unwrap();
+        let values: Vec<uint> = set.iter().map(|&v| v).collect();
+        assert!(values == vec!(1, 3, 7, 9));
+    }
+
+    #[test]
+    fn duplicate_policy()
+    {
+        let mut decoder = json::Decoder::new(json::from_str("[1, 2, 2, 3]").unwrap());
+        match decode_set::<json::DecoderError, json::Decoder, uint>(&mut decoder, RejectDuplicates) {
+            Err(DuplicateKey(2)) => (),
+            _ => fail!("expected duplicate at 2")
+        }
+
+        let mut decoder = json::Decoder::new(json::from_str("[4, 2, 4, 3]").unwrap());
+        match decode_set::<json::DecoderError, json::Decoder, uint>(&mut decoder, RejectDuplicates) {
+            Err(DuplicateKey(2)) => (),
+            _ => fail!("expected duplicate at 2")
+        }
+
+        let mut decoder = json::Decoder::new(json::from_str("[1, 2, 2, 3]").unwrap());
+        match decode_set::<json::DecoderError, json::Decoder, uint>(&mut decoder, KeepFirst) {
+            Ok(set) => assert!(set.len() == 3),
+            _ => fail!("duplicates should be merged")
+        }
+    }
+
+    #[test]
+    fn from_sorted_vec_insert()
+    {
+        let mut map = BTreeMap::from_sorted_vec(range(0, 10_000u).map(|i| (i*2, i*2)).collect());
+        for i in range(0, 10_000u) {
+            assert!(map.insert(i*2+1, i*2+1) == false);
+        }
+        for i in range(0, 20_000u) {
+            assert!(*map.find(&i).unwrap() == i);
+        }
+        for i in range(0, 20_000u) {
+            assert!(map.pop(&i).unwrap() == i);
+        }
+        assert!(map.len() == 0);
+    }
+
+    // unsorted input is inserted entry by entry, later entries win
+    #[test]
+    fn from_sorted_vec_unsorted()
+    {
+        let map = BTreeMap::from_sorted_vec(vec!((3u, 0u), (1, 1), (2, 2), (1, 3)));
+        assert!(map.len() == 3);
+        assert!(map.iter().map(|(&k, &v)| (k, v)).collect::<Vec<(uint, uint)>>() == vec!((1, 3), (2, 2), (3, 0)));
+
+        let set = BTreeSet::from_sorted_vec(vec!(5u, 4, 4, 1));
+        assert!(set.iter().map(|&v| v).collect::<Vec<uint>>() == vec!(1, 4, 5));
+    }
+}
+
+mod snapshot {
+    use cow::btree::BTreeMap;
+    use cow::snapshot::{write_snapshot, read_snapshot, SNAPSHOT_VERSION};
+    use cow::crc::crc32;
+    use std::io::{IoResult, MemWriter, MemReader};
+
+    fn roundtrip_n(len: uint)
+    {
+        let mut map: BTreeMap<uint, (u32, bool)> = BTreeMap::new();
+        for i in range(0, len) {
+            map.insert(i * 3, (i as u32, i % 2 == 0));
+        }
+
+        let mut w = MemWriter::new();
+        write_snapshot(&map, &mut w).unwrap();
+
+        let mut r = MemReader::new(w.unwrap());
+        let mut copy: BTreeMap<uint, (u32, bool)> = read_snapshot(&mut r).unwrap();
+
+        assert!(copy.len() == len);
+        for (a, b) in map.iter().zip(copy.iter()) {
+            assert!(a == b);
+        }
+
+        // the loaded tree must still be a valid tree
+        for i in range(0, len) {
+            copy.insert(i * 3 + 1, (0, false));
+        }
+        for i in range(0, len) {
+            assert!(copy.pop(&(i * 3)).unwrap() == (i as u32, i % 2 == 0));
+        }
+        assert!(copy.len() == len);
+    }
+
+    #[test]
+    fn roundtrip_0() { roundtrip_n(0) }
+    #[test]
+    fn roundtrip_10() { roundtrip_n(10) }
+    #[test]
+    fn roundtrip_990() { roundtrip_n(990) }
+    #[test]
+    fn roundtrip_100_000() { roundtrip_n(100_000) }
+
+    #[test]
+    fn corrupt()
+    {
+        let mut map: BTreeMap<uint, uint> = BTreeMap::new();
+        for i in range(0, 1000u) {
+            map.insert(i, i);
+        }
+
+        let mut w = MemWriter::new();
+        write_snapshot(&map, &mut w).unwrap();
+        let mut buf = w.unwrap();
+        buf[100] ^= 1;
+
+        let mut r = MemReader::new(buf);
+        let res: IoResult<BTreeMap<uint, uint>> = read_snapshot(&mut r);
+        assert!(res.is_err());
+    }
+
+    // a corrupt length runs into the end of the input instead of
+    // allocating what it claims
+    #[test]
+    fn huge_length()
+    {
+        let mut w = MemWriter::new();
+        w.write(bytes!("COWS")).unwrap();
+        w.write_le_u32(SNAPSHOT_VERSION).unwrap();
+        w.write_u8(1).unwrap();
+        w.write_le_u32(1).unwrap();
+        w.write_le_u32(0xffff_fff0).unwrap();
+        w.write(bytes!("abc")).unwrap();
+
+        let mut r = MemReader::new(w.unwrap());
+        let res: IoResult<BTreeMap<~str, uint>> = read_snapshot(&mut r);
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn crc()
+    {
+        assert!(crc32(bytes!("123456789")) == 0xcbf43926);
+    }
+}
+
+mod wal {
+    use cow::wal::DurableBTreeMap;
+    use std::io;
+    use std::io::{IoResult, File, TempDir};
+    use std::io::fs;
+
+    fn check(map: &DurableBTreeMap<uint, uint>, len: uint)
+    {
+        assert!(map.map().len() == len / 2);
+        for i in range(0, len) {
+            if i % 2 == 0 {
+                assert!(map.map().find(&i).is_none());
+            } else {
+                assert!(*map.map().find(&i).unwrap() == i + 1);
+            }
+        }
+    }
+
+    fn fill(map: &mut DurableBTreeMap<uint, uint>, len: uint)
+    {
+        for i in range(0, len) {
+            map.insert(i, i).unwrap();
+        }
+        for i in range(0, len) {
+            if i % 2 == 0 {
+                map.pop(&i).unwrap();
+            } else {
+                map.swap(i, i + 1).unwrap();
+            }
+        }
+    }
+
+    fn recover_n(len: uint)
+    {
+        let dir = TempDir::new("cow-wal").unwrap();
+        {
+            let mut map = DurableBTreeMap::open_with_segment_size(dir.path(), 4096).unwrap();
+            fill(&mut map, len);
+            check(&map, len);
+        }
+
+        let map = DurableBTreeMap::open(dir.path()).unwrap();
+        check(&map, len);
+    }
+
+    fn checkpoint_n(len: uint)
+    {
+        let dir = TempDir::new("cow-wal").unwrap();
+        {
+            let mut map = DurableBTreeMap::open_with_segment_size(dir.path(), 4096).unwrap();
+            for i in range(0, len) {
+                map.insert(i, 0).unwrap();
+            }
+            map.checkpoint().unwrap();
+            fill(&mut map, len);
+        }
+
+        let mut map = DurableBTreeMap::open(dir.path()).unwrap();
+        check(&map, len);
+        map.checkpoint().unwrap();
+
+        let map = DurableBTreeMap::open(dir.path()).unwrap();
+        check(&map, len);
+    }
+
+    // names of the log segments, oldest first
+    fn segments(dir: &Path) -> Vec<~str>
+    {
+        let mut names: Vec<~str> = fs::readdir(dir).unwrap().iter()
+            .filter_map(|p| p.filename_str())
+            .filter(|name| name.starts_with("wal-"))
+            .map(|name| name.to_owned()).collect();
+        names.as_mut_slice().sort();
+        names
+    }
+
+    // a crash in the middle of writing the last record, then more
+    // records written after the restart
+    fn torn_n(len: uint)
+    {
+        let dir = TempDir::new("cow-wal").unwrap();
+        {
+            let mut map = DurableBTreeMap::open_with_segment_size(dir.path(), 4096).unwrap();
+            for i in range(0, len) {
+                map.insert(i, i).unwrap();
+            }
+        }
+
+        {
+            let path = dir.path().join(segments(dir.path()).pop().unwrap());
+            let mut file = File::open_mode(&path, io::Append, io::Write).unwrap();
+            file.write_le_u32(100).unwrap();
+            file.write_le_u32(0).unwrap();
+            file.write([1u8, 2, 3]).unwrap();
+        }
+
+        {
+            let mut map = DurableBTreeMap::open_with_segment_size(dir.path(), 4096).unwrap();
+            assert!(map.map().len() == len);
+            for i in range(len, 2 * len) {
+                map.insert(i, i).unwrap();
+            }
+        }
+
+        let map: DurableBTreeMap<uint, uint> = DurableBTreeMap::open(dir.path()).unwrap();
+        assert!(map.map().len() == 2 * len);
+        for i in range(0, 2 * len) {
+            assert!(*map.map().find(&i).unwrap() == i);
+        }
+    }
+
+    #[test]
+    fn recover_10() { recover_n(10) }
+    #[test]
+    fn recover_990() { recover_n(990) }
+    #[test]
+    fn recover_10_000() { recover_n(10_000) }
+
+    #[test]
+    fn torn_10() { torn_n(10) }
+    #[test]
+    fn torn_990() { torn_n(990) }
+
+    #[test]
+    fn checkpoint_10() { checkpoint_n(10) }
+    #[test]
+    fn checkpoint_990() { checkpoint_n(990) }
+    #[test]
+    fn checkpoint_10_000() { checkpoint_n(10_000) }
+
+    // a batch that could not be written is kept, and nothing is
+    // snapshotted until it is
+    #[test]
+    fn failed_commit()
+    {
+        let dir = TempDir::new("cow-wal").unwrap();
+        let path = dir.path().join("log");
+        {
+            let mut map = DurableBTreeMap::open_with_segment_size(&path, 1).unwrap();
+            map.insert(0u, 0u).unwrap();
+
+            // every append starts a new segment, which fails without the
+            // directory
+            fs::rmdir_recursive(&path).unwrap();
+            map.begin_batch();
+            map.insert(1, 1).unwrap();
+            assert!(map.commit_batch().is_err());
+            assert!(map.checkpoint().is_err());
+            map.insert(2, 2).unwrap();
+
+            fs::mkdir(&path, io::UserRWX).unwrap();
+            map.commit_batch().unwrap();
+        }
+
+        let map: DurableBTreeMap<uint, uint> = DurableBTreeMap::open(&path).unwrap();
+        assert!(map.map().len() == 2);
+        assert!(map.map().find(&1) == Some(&1));
+        assert!(map.map().find(&2) == Some(&2));
+    }
+
+    // a bad record in front of later ones is not a torn write
+    #[test]
+    fn corrupt()
+    {
+        let dir = TempDir::new("cow-wal").unwrap();
+        {
+            let mut map = DurableBTreeMap::open_with_segment_size(dir.path(), 4096).unwrap();
+            for i in range(0, 1000u) {
+                map.insert(i, i).unwrap();
+            }
+        }
+
+        let names = segments(dir.path());
+        assert!(names.len() > 1);
+        let path = dir.path().join(names.get(0).as_slice());
+        let mut data = File::open(&path).unwrap().read_to_end().unwrap();
+        data[20] ^= 0xff;
+        File::create(&path).unwrap().write(data.as_slice()).unwrap();
+
+        let map: IoResult<DurableBTreeMap<uint, uint>> = DurableBTreeMap::open(dir.path());
+        assert!(map.is_err());
+        assert!(segments(dir.path()) == names);
+    }
+
+    #[test]
+    fn batch()
+    {
+        let dir = TempDir::new("cow-wal").unwrap();
+        {
+            let mut map: DurableBTreeMap<uint, uint> = DurableBTreeMap::open(dir.path()).unwrap();
+            map.begin_batch();
+            for i in range(0, 1000u) {
+                map.insert(i, i).unwrap();
+            }
+            map.commit_batch().unwrap();
+
+            // never committed so lost on recovery
+            map.begin_batch();
+            map.insert(1000, 1000).unwrap();
+        }
+
+        let map: DurableBTreeMap<uint, uint> = DurableBTreeMap::open(dir.path()).unwrap();
+        assert!(map.map().len() == 1000);
+        assert!(map.map().find(&1000).is_none());
+    }
+}
+
+mod par {
+    use cow::btree::BTreeMap;
+    use cow::par::{par_fold, par_map_reduce};
+
+    fn build(len: uint) -> BTreeMap<uint, uint>
+    {
+        let mut map = BTreeMap::new();
+        for i in range(0, len) {
+            map.insert(i, i);
+        }
+        map
+    }
+
+    fn sum(acc: uint, _: &uint, v: &uint) -> uint { acc + *v }
+    fn add(a: uint, b: uint) -> uint { a + b }
+    fn value(_: &uint, v: &uint) -> uint { *v }
+
+    fn push(acc: Vec<uint>, k: &uint, _: &uint) -> Vec<uint>
+    {
+        let mut acc = acc;
+        acc.push(*k);
+        acc
+    }
+
+    fn append(a: Vec<uint>, b: Vec<uint>) -> Vec<uint>
+    {
+        let mut a = a;
+        a.push_all_move(b);
+        a
+    }
+
+    fn chunks_n(len: uint, n: uint)
+    {
+        let map = build(len);
+        let chunks = map.par_chunks(n);
+
+        assert!(chunks.len() <= n);
+        if len > 10_000 {
+            assert!(chunks.len() == n);
+        }
+        assert!(map.split_subtrees(n).len() == chunks.len());
+
+        let mut expected = 0;
+        for chunk in chunks.move_iter() {
+            for (&k, _) in chunk {
+                assert!(k == expected);
+                expected += 1;
+            }
+        }
+        assert!(expected == len);
+    }
+
+    fn fold_n(len: uint)
+    {
+        let map = build(len);
+        let expected = range(0, len).fold(0, |a, b| a + b);
+        assert!(par_fold(&map, 8, 0u, 0u, sum, add) == expected);
+        match par_map_reduce(&map, 8, value, add) {
+            Some(total) => assert!(total == expected),
+            None => assert!(len == 0)
+        }
+    }
+
+    // init is not an identity, the result still has to match folding
+    // the entries one after another
+    fn fold_init_n(len: uint)
+    {
+        let map = build(len);
+        let expected = map.iter().fold(1u, |acc, (k, v)| sum(acc, k, v));
+        assert!(par_fold(&map, 8, 1u, 0u, sum, add) == expected);
+
+        let expected = map.iter().fold(vec!(len), |acc, (k, v)| push(acc, k, v));
+        assert!(par_fold(&map, 8, vec!(len), Vec::new(), push, append) == expected);
+    }
+
+    #[test]
+    fn chunks_0() { chunks_n(0, 4) }
+    #[test]
+    fn chunks_10() { chunks_n(10, 4) }
+    #[test]
+    fn chunks_990() { chunks_n(990, 4) }
+    #[test]
+    fn chunks_100_000() { chunks_n(100_000, 8) }
+    #[test]
+    fn chunks_100_000_many() { chunks_n(100_000, 100) }
+
+    #[test]
+    fn fold_0() { fold_n(0) }
+    #[test]
+    fn fold_10() { fold_n(10) }
+    #[test]
+    fn fold_990() { fold_n(990) }
+    #[test]
+    fn fold_100_000() { fold_n(100_000) }
+
+    #[test]
+    fn fold_init_0() { fold_init_n(0) }
+    #[test]
+    fn fold_init_10() { fold_init_n(10) }
+    #[test]
+    fn fold_init_990() { fold_init_n(990) }
+    #[test]
+    fn fold_init_100_000() { fold_init_n(100_000) }
+}
+
+mod table {
+    use cow::table::Table;
+
+    #[deriving(Clone, Default)]
+    struct User {
+        name: ~str,
+        city: ~str,
+        age: uint
+    }
+
+    fn city(user: &User) -> ~str { user.city.clone() }
+    fn age(user: &User) -> uint { user.age }
+
+    fn user(name: &str, city: &str, age: uint) -> User
+    {
+        User {name: name.to_owned(), city: city.to_owned(), age: age}
+    }
+
+    fn names(users: Vec<&User>) -> Vec<~str>
+    {
+        users.iter().map(|u| u.name.clone()).collect()
+    }
+
+    #[test]
+    fn test_indexes()
+    {
+        let mut table = Table::new();
+        table.insert(1u, user("ann", "oslo", 30));
+        table.insert(2u, user("bob", "rome", 40));
+        assert!(table.add_index("city", city));
+        table.insert(3u, user("eve", "oslo", 40));
+        assert!(table.add_index("age", age));
+
+        assert!(names(table.lookup("city", &~"oslo").unwrap()) == vec!(~"ann", ~"eve"));
+        assert!(names(table.lookup("age", &40u).unwrap()) == vec!(~"bob", ~"eve"));
+        assert!(table.lookup("city", &~"paris").unwrap().len() == 0);
+
+        // wrong key type or no such index
+        assert!(table.lookup("city", &1u).is_none());
+        assert!(table.lookup("missing", &~"oslo").is_none());
+        assert!(table.index::<uint>("city").is_none());
+        assert!(table.index::<uint>("missing").is_none());
+        assert!(table.index::<~str>("city").unwrap().len() == 2);
+
+        assert!(table.update(&1, |u| u.city = ~"rome"));
+        assert!(!table.update(&9, |u| u.age = 0));
+        assert!(names(table.lookup("city", &~"oslo").unwrap()) == vec!(~"eve"));
+        assert!(names(table.lookup("city", &~"rome").unwrap()) == vec!(~"ann", ~"bob"));
+
+        let old = table.insert(2, user("bob", "oslo", 41));
+        assert!(old.unwrap().age == 40);
+        assert!(names(table.lookup("age", &40u).unwrap()) == vec!(~"eve"));
+
+        assert!(table.remove(&3).is_some());
+        assert!(table.remove(&3).is_none());
+        assert!(names(table.lookup("city", &~"oslo").unwrap()) == vec!(~"bob"));
+        assert!(table.index::<uint>("age").unwrap().find(&40).is_none());
+        assert!(table.len() == 2);
+    }
+
+    #[test]
+    fn test_snapshot()
+    {
+        let mut table = Table::new();
+        table.add_index("city", city);
+        for i in range(0, 1000u) {
+            table.insert(i, user("x", if i % 2 == 0 { "oslo" } else { "rome" }, i));
+        }
+
+        let snapshot = table.clone();
+        for i in range(0, 500u) {
+            table.remove(&i);
+        }
+        table.update(&999, |u| u.city = ~"oslo");
+
+        assert!(snapshot.len() == 1000);
+        assert!(snapshot.lookup("city", &~"oslo").unwrap().len() == 500);
+        assert!(snapshot.get(&999).unwrap().city == ~"rome");
+        assert!(table.len() == 500);
+        assert!(table.lookup("city", &~"oslo").unwrap().len() == 251);
+    }
+
+    #[test]
+    fn test_duplicate_index()
+    {
+        let mut table: Table<uint, User> = Table::new();
+        table.insert(1, user("ann", "oslo", 30));
+        assert!(table.add_index("city", city));
+        assert!(!table.add_index("city", age));
+        assert!(table.index::<~str>("city").is_some());
+        assert!(table.index::<uint>("city").is_none());
+    }
+}
+
+mod datalog {
+    use cow::btree::BTreeSet;
+    use cow::datalog::Variable;
+
+    fn closure_n(len: uint)
+    {
+        // a chain 0 -> 1 -> .. -> len plus a few shortcuts
+        let mut edges = BTreeSet::new();
+        for i in range(0, len) {
+            edges.insert((i, i + 1));
+            if i % 7 == 0 {
+                edges.insert((i, i + 3));
+            }
+        }
+
+        // path(x, z) :- edge(x, z)
+        // path(x, z) :- path(x, y), edge(y, z)
+        let mut edge: Variable<(uint, uint)> = Variable::from_set(&edges);
+        // paths are keyed by their end so they can be joined to edges
+        let mut path: Variable<(uint, uint)> = Variable::new();
+        while path.changed() | edge.changed() {
+            path.from_map(&edge, |&(x, z)| (z, x));
+            path.from_join(&path, &edge, |_, &x, &z| (z, x));
+        }
+
+        let paths = path.complete();
+        assert!(paths.len() == len * (len + 1) / 2);
+        for &(z, x) in paths.iter() {
+            assert!(x < z && z <= len);
+        }
+    }
+
+    #[test]
+    fn closure_10() { closure_n(10) }
+    #[test]
+    fn closure_100() { closure_n(100) }
+
+    #[test]
+    fn test_filter()
+    {
+        let mut numbers: Variable<uint> = Variable::new();
+        let mut even: Variable<uint> = Variable::new();
+        numbers.insert(0);
+        while numbers.changed() | even.changed() {
+            numbers.from_map(&numbers, |&n| if n < 100 { n + 1 } else { n });
+            even.from_filter(&numbers, |&n| n % 2 == 0);
+        }
+        assert!(numbers.all().len() == 101);
+        assert!(even.complete().len() == 51);
+    }
+}
+
+mod view {
+    use cow::btree::BTreeMap;
+    use cow::view::{ViewDriver, View, CountView, InvertedIndex};
+
+    fn parity(_: &uint, v: &uint) -> bool { v % 2 == 0 }
+    fn digits(v: &uint) -> Vec<uint> { vec!(v % 10, v / 10 % 10) }
+
+    fn recount(map: &BTreeMap<uint, uint>) -> (uint, uint)
+    {
+        let even = map.iter().filter(|&(_, v)| v % 2 == 0).count();
+        (even, map.len() - even)
+    }
+
+    fn views_n(len: uint)
+    {
+        let mut map = BTreeMap::new();
+        for i in range(0, len) {
+            map.insert(i, i);
+        }
+
+        let mut driver = ViewDriver::new();
+        let mut counts = CountView::new(parity);
+        let mut index = InvertedIndex::new(digits);
+
+        assert!(driver.refresh(&map, &mut [&mut counts as &mut View<uint, uint>, &mut index as &mut View<uint, uint>]) == len);
+
+        for i in range(0, len / 3) {
+            map.pop(&(i * 3));
+            map.insert(i * 3 + 1, i * 3 + 2);
+        }
+        map.insert(len, 1);
+        driver.refresh(&map, &mut [&mut counts as &mut View<uint, uint>, &mut index as &mut View<uint, uint>]);
+        // nothing changed since
+        assert!(driver.refresh(&map, &mut [&mut counts as &mut View<uint, uint>]) == 0);
+
+        let (even, odd) = recount(&map);
+        assert!(counts.counts().find(&true).map_or(0, |&c| c) == even);
+        assert!(counts.counts().find(&false).map_or(0, |&c| c) == odd);
+
+        for (k, v) in map.iter() {
+            for d in digits(v).iter() {
+                assert!(index.index().find(d).unwrap().contains(k));
+            }
+        }
+        let total = index.index().iter().fold(0, |s, (_, keys)| s + keys.len());
+        let expected = map.iter().fold(0, |s, (_, v)| {
+            let d = digits(v);
+            s + if d.get(0) == d.get(1) { 1 } else { 2 }
+        });
+        assert!(total == expected);
+    }
+
+    #[test]
+    fn views_10() { views_n(10) }
+    #[test]
+    fn views_1_000() { views_n(1_000) }
+    #[test]
+    fn views_100_000() { views_n(100_000) }
+}
+
+mod observed {
+    use cow::btree::BTreeMap;
+    use cow::observed::{ObservedBTreeMap, Observer, Event, Insert, Swap, Pop, Clear};
+
+    struct Counter {
+        tx: Sender<uint>
+    }
+
+    impl Observer<uint, uint> for Counter {
+        fn notify(&mut self, batch: &[Event<uint, uint>])
+        {
+            self.tx.send(batch.len());
+        }
+    }
+
+    #[test]
+    fn test_events()
+    {
+        let mut map = ObservedBTreeMap::new(BTreeMap::new());
+        map.insert(0u, 0u);
+        let rx = map.subscribe_channel();
+
+        map.insert(1, 10);
+        assert!(map.swap(1, 11) == Some(10));
+        assert!(map.pop(&1) == Some(11));
+        assert!(map.pop(&1) == None);
+        map.clear();
+
+        match rx.recv().as_slice() {
+            [Insert(1, 10)] => (),
+            _ => fail!("expected an insert")
+        }
+        match rx.recv().as_slice() {
+            [Swap(1, 11, Some(10))] => (),
+            _ => fail!("expected a swap")
+        }
+        match rx.recv().as_slice() {
+            [Pop(1, 11)] => (),
+            _ => fail!("expected a pop")
+        }
+        match rx.recv().as_slice() {
+            [Clear] => (),
+            _ => fail!("expected a clear")
+        }
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_batch()
+    {
+        let mut map = ObservedBTreeMap::new(BTreeMap::new());
+        let (tx, rx) = channel();
+        let id = map.subscribe(~Counter {tx: tx} as ~Observer<uint, uint>);
+        let events = map.subscribe_channel();
+
+        map.begin_batch();
+        for i in range(0, 100u) {
+            map.insert(i, i);
+        }
+        for i in range(0, 50u) {
+            map.pop(&i);
+        }
+        assert!(rx.try_recv().is_err());
+        map.commit_batch();
+        assert!(rx.recv() == 150);
+        assert!(events.recv().len() == 150);
+
+        // an empty batch is not delivered
+        map.begin_batch();
+        map.commit_batch();
+        assert!(rx.try_recv().is_err());
+
+        assert!(map.unsubscribe(id));
+        assert!(!map.unsubscribe(id));
+        map.insert(1000, 0);
+        assert!(rx.try_recv().is_err());
+        assert!(events.recv().len() == 1);
+        assert!(map.map().len() == 51);
+    }
+}
+
+mod multimap {
+    use cow::multimap::BTreeMultiMap;
+
+    fn multimap_n(len: uint)
+    {
+        let mut map = BTreeMultiMap::new();
+        for i in range(0, len) {
+            for j in range(0, i % 5) {
+                assert!(!map.insert(i, j * 10));
+            }
+        }
+        assert!(map.insert(1, 0));
+
+        for i in range(0, len) {
+            assert!(map.count(&i) == i % 5);
+            let values: Vec<uint> = map.get_all(&i).map(|&v| v).collect();
+            let expected: Vec<uint> = range(0, i % 5).map(|j| j * 10).collect();
+            assert!(values == expected);
+        }
+        assert!(map.count(&len) == 0);
+        assert!(map.get_all(&len).next().is_none());
+
+        let snapshot = map.clone();
+        for i in range(0, len) {
+            if i % 2 == 0 {
+                assert!(map.remove_all(&i) == i % 5);
+            } else if i % 5 != 0 {
+                assert!(map.remove_one(&i, &0));
+                assert!(!map.remove_one(&i, &0));
+            }
+        }
+
+        for i in range(0, len) {
+            let expected = if i % 2 == 0 || i % 5 == 0 { 0 } else { i % 5 - 1 };
+            assert!(map.count(&i) == expected);
+            assert!(!map.contains(&i, &0));
+            assert!(snapshot.count(&i) == i % 5);
+        }
+    }
+
+    #[test]
+    fn multimap_10() { multimap_n(10) }
+    #[test]
+    fn multimap_1_000() { multimap_n(1_000) }
+    #[test]
+    fn multimap_100_000() { multimap_n(100_000) }
+}
+
+mod interval {
+    use std::iter::range_step;
+    use cow::btree::IntervalMap;
+
+    fn interval(i: uint) -> (uint, uint)
+    {
+        (i * 3, i * 3 + i % 7 + 1)
+    }
+
+    fn interval_n(len: uint)
+    {
+        let mut map = IntervalMap::new();
+        for i in range(0, len) {
+            let (start, end) = interval(i);
+            assert!(!map.insert(start, end, i));
+        }
+        // spans everything, it has to be found from any leaf
+        assert!(!map.insert(1, len * 3, len));
+        assert!(map.len() == len + 1);
+
+        for p in range_step(0, len * 3 + 10, if len > 1_000 { 997 } else { 1 }) {
+            let found: Vec<(uint, uint)> = map.containing(&p).map(|(&s, &e, _)| (s, e)).collect();
+            let mut expected: Vec<(uint, uint)> = range(0, len).map(|i| interval(i))
+                .filter(|&(s, e)| s <= p && p < e).collect();
+            if 1 <= p && p < len * 3 {
+                expected.push((1, len * 3));
+            }
+            expected.sort();
+            assert!(found == expected);
+        }
+
+        for lo in range_step(0, len * 3, if len > 1_000 { 4999 } else { 7 }) {
+            let hi = lo + 10;
+            let found: Vec<(uint, uint)> = map.overlapping(&lo, &hi).map(|(&s, &e, _)| (s, e)).collect();
+            let mut expected: Vec<(uint, uint)> = range(0, len).map(|i| interval(i))
+                .filter(|&(s, e)| s < hi && e > lo).collect();
+            expected.push((1, len * 3));
+            expected.sort();
+            assert!(found == expected);
+        }
+        assert!(map.overlapping(&5, &5).next().is_none());
+
+        // empty intervals are ignored
+        assert!(!map.insert(5, 5, 0));
+        assert!(!map.insert(6, 5, 0));
+        assert!(map.len() == len + 1);
+
+        let snapshot = map.clone();
+        for i in range(0, len) {
+            let (start, end) = interval(i);
+            if i % 2 == 0 {
+                assert!(map.remove(&start, &end) == Some(i));
+            }
+        }
+        assert!(map.remove(&1, &(len * 3)) == Some(len));
+
+        for i in range(0, len) {
+            let (start, end) = interval(i);
+            let found = map.containing(&start).any(|(&s, &e, _)| s == start && e == end);
+            assert!(found == (i % 2 != 0));
+            assert!(snapshot.find(&start, &end) == Some(&i));
+        }
+        assert!(snapshot.len() == len + 1);
+    }
+
+    #[test]
+    fn interval_10() { interval_n(10) }
+    #[test]
+    fn interval_1_000() { interval_n(1_000) }
+    #[test]
+    fn interval_100_000() { interval_n(100_000) }
+}
+
+mod summary {
+    use std::iter::range_step;
+    use cow::btree::{SummaryMap, Summary};
+
+    #[deriving(Clone, Default, Eq)]
+    struct Total {
+        sum: uint,
+        count: uint
+    }
+
+    impl Summary<uint, uint> for Total
+    {
+        fn summarize(_: &uint, value: &uint) -> Total
+        {
+            Total {sum: *value, count: 1}
+        }
+
+        fn combine(&mut self, other: &Total)
+        {
+            self.sum += other.sum;
+            self.count += other.count;
+        }
+    }
+
+    // the first and last key, only right if combined in order
+    #[deriving(Clone, Default)]
+    struct Span {
+        first: Option<uint>,
+        last: Option<uint>
+    }
+
+    impl Summary<uint, uint> for Span
+    {
+        fn summarize(key: &uint, _: &uint) -> Span
+        {
+            Span {first: Some(*key), last: Some(*key)}
+        }
+
+        fn combine(&mut self, other: &Span)
+        {
+            if self.first.is_none() {
+                self.first = other.first;
+            }
+            if other.last.is_some() {
+                self.last = other.last;
+            }
+        }
+    }
+
+    fn value(i: uint) -> uint
+    {
+        if i % 3 == 0 { i * 10 } else { i }
+    }
+
+    fn present(i: uint) -> bool
+    {
+        i % 5 != 1
+    }
+
+    fn expected(len: uint, lo: uint, hi: uint) -> Total
+    {
+        let mut total = Total {sum: 0, count: 0};
+        for i in range(0, len) {
+            if present(i) && lo <= i * 2 && i * 2 < hi {
+                total.sum += value(i);
+                total.count += 1;
+            }
+        }
+        total
+    }
+
+    fn summary_n(len: uint)
+    {
+        let mut map: SummaryMap<uint, uint, Total> = SummaryMap::new();
+        for i in range(0, len) {
+            assert!(!map.insert(i * 2, i));
+        }
+        for i in range_step(0, len, 3) {
+            assert!(map.insert(i * 2, value(i)));
+        }
+        for i in range_step(1, len, 5) {
+            assert!(map.pop(&(i * 2)) == Some(value(i)));
+        }
+        assert!(map.summary() == expected(len, 0, len * 2));
+
+        let step = len / 7 + 1;
+        for lo in range_step(0, len * 2 + 2, step) {
+            for hi in range_step(lo, len * 2 + 2, step * 3) {
+                assert!(map.fold_range(Some(&lo), Some(&hi)) == expected(len, lo, hi));
+            }
+            assert!(map.fold_range(Some(&lo), None) == expected(len, lo, len * 2));
+            assert!(map.fold_range(None, Some(&lo)) == expected(len, 0, lo));
+        }
+        assert!(map.fold_range(Some(&4), Some(&2)) == Total {sum: 0, count: 0});
+
+        // a clone keeps its summaries while the original changes
+        let snapshot = map.clone();
+        for i in range(0, len) {
+            map.insert(i * 2, 1);
+        }
+        assert!(map.summary() == Total {sum: len, count: len});
+        assert!(snapshot.summary() == expected(len, 0, len * 2));
+    }
+
+    #[test]
+    fn summary_10() { summary_n(10) }
+    #[test]
+    fn summary_1_000() { summary_n(1_000) }
+    #[test]
+    fn summary_100_000() { summary_n(100_000) }
+
+    #[test]
+    fn summary_order()
+    {
+        let map: SummaryMap<uint, uint, Span> = SummaryMap::from_sorted_vec(range(0, 10_000u).map(|i| (i, i)).collect());
+        for lo in range_step(0, 10_000u, 37) {
+            let span = map.fold_range(Some(&lo), Some(&(lo + 500)));
+            assert!(span.first == Some(lo));
+            assert!(span.last == Some(::std::cmp::min(lo + 499, 9_999)));
+        }
+    }
+}
+
+mod ranges {
+    use cow::ranges::{RangeMap, RangeSet};
+
+    // applies the same operations to a RangeMap and to one cell per point
+    fn ranges_n(len: uint)
+    {
+        let size = len * 4;
+        let mut map = RangeMap::new();
+        let mut cells: Vec<Option<uint>> = Vec::from_elem(size, None);
+
+        for i in range(0, len) {
+            let start = (i * 7919) % size;
+            let end = ::std::cmp::min(start + i % 13 + 1, size);
+            if i % 4 == 3 {
+                map.remove_range(&start, &end);
+                for p in range(start, end) {
+                    *cells.get_mut(p) = None;
+                }
+            } else {
+                map.insert_range(start, end, i % 3);
+                for p in range(start, end) {
+                    *cells.get_mut(p) = Some(i % 3);
+                }
+            }
+        }
+
+        for p in range(0, size) {
+            assert!(map.get(&p).map(|&v| v) == *cells.get(p));
+        }
+
+        // ranges are disjoint, and the ones that touch differ in value
+        let mut last: Option<(uint, uint)> = None;
+        for (&start, &end, &value) in map.iter() {
+            assert!(start < end);
+            for p in range(start, end) {
+                assert!(*cells.get(p) == Some(value));
+            }
+            match last {
+                Some((e, v)) => assert!(e < start || (e == start && v != value)),
+                None => ()
+            }
+            last = Some((end, value));
+        }
+
+        let gaps: Vec<(uint, uint)> = map.gaps(&1, &(size - 1)).collect();
+        let mut expected = Vec::new();
+        let mut p = 1;
+        while p < size - 1 {
+            if cells.get(p).is_none() {
+                let start = p;
+                while p < size - 1 && cells.get(p).is_none() {
+                    p += 1;
+                }
+                expected.push((start, p));
+            } else {
+                p += 1;
+            }
+        }
+        assert!(gaps == expected);
+
+        let snapshot = map.clone();
+        map.insert_range(0, size, 7);
+        assert!(map.len() == 1);
+        for p in range(0, size) {
+            assert!(snapshot.get(&p).map(|&v| v) == *cells.get(p));
+        }
+    }
+
+    #[test]
+    fn ranges_10() { ranges_n(10) }
+    #[test]
+    fn ranges_1_000() { ranges_n(1_000) }
+    #[test]
+    fn ranges_100_000() { ranges_n(100_000) }
+
+    #[test]
+    fn range_set()
+    {
+        let mut set = RangeSet::new();
+        set.insert_range(10u, 20u);
+        set.insert_range(20, 30);
+        set.insert_range(25, 40);
+        set.insert_range(50, 60);
+        assert!(set.len() == 2);
+        assert!(set.iter().map(|(&s, &e)| (s, e)).collect::<Vec<(uint, uint)>>() == vec!((10, 40), (50, 60)));
+
+        set.remove_range(&15, &17);
+        assert!(set.contains(&14) && !set.contains(&15) && !set.contains(&16) && set.contains(&17));
+        assert!(set.gaps(&0, &100).collect::<Vec<(uint, uint)>>() == vec!((0, 10), (15, 17), (40, 50), (60, 100)));
+        assert!(set.gaps(&12, &14).next().is_none());
+        assert!(set.gaps(&45, &55).collect::<Vec<(uint, uint)>>() == vec!((45, 50)));
+    }
+}
+
+mod vec {
+    use std::cmp::min;
+    use cow::vec::CowVec;
+    use rand::{Rng, IsaacRng, SeedableRng};
+
+    fn check(v: &CowVec<uint>, expected: &Vec<uint>)
+    {
+        v.check();
+        assert!(v.len() == expected.len());
+        assert!(v.iter().zip(expected.iter()).all(|(a, b)| a == b));
+    }
+
+    fn vec_n(len: uint)
+    {
+        let mut v = CowVec::new();
+        let mut expected = Vec::new();
+        for i in range(0, len) {
+            v.push(i);
+            expected.push(i);
+        }
+        check(&v, &expected);
+        for i in range(0, len) {
+            assert!(*v.get(i) == i);
+        }
+
+        let snapshot = v.clone();
+        let ops = min(len, 2_000);
+        for i in range(0, ops) {
+            let idx = (i * 7919) % (expected.len() + 1);
+            match i % 4 {
+                0 => {
+                    v.insert(idx, len + i);
+                    expected.insert(idx, len + i);
+                },
+                1 if idx < expected.len() => {
+                    assert!(v.remove(idx) == expected.remove(idx).unwrap());
+                },
+                2 if idx < expected.len() => {
+                    v.set(idx, i);
+                    *expected.get_mut(idx) = i;
+                },
+                _ => assert!(v.pop() == expected.pop())
+            }
+        }
+        check(&v, &expected);
+        check(&snapshot, &range(0, len).collect());
+
+        for at in vec!(0, len / 3, len / 2, len).move_iter() {
+            let (left, right) = snapshot.split_at(at);
+            assert!(left.len() == at && right.len() == len - at);
+            check(&left, &range(0, at).collect());
+            check(&right, &range(at, len).collect());
+            check(&left.concat(&right), &range(0, len).collect());
+            check(&right.concat(&left), &range(at, len).chain(range(0, at)).collect());
+        }
+
+        // a small vec joined to a large one on either side
+        let mut small = CowVec::new();
+        for i in range(0, 3u) {
+            small.push(len + i);
+        }
+        check(&snapshot.concat(&small), &range(0, len + 3).collect());
+        check(&small.concat(&snapshot), &range(len, len + 3).chain(range(0, len)).collect());
+
+        while v.len() != 0 {
+            assert!(v.pop() == expected.pop());
+        }
+        assert!(v.pop().is_none());
+        check(&snapshot, &range(0, len).collect());
+    }
+
+    // cut a piece out at a random place and put it at the end, over and
+    // over. Half the pieces are short so concat also joins trees of
+    // different heights.
+    fn split_concat_n(len: uint)
+    {
+        let mut rng = IsaacRng::new().unwrap();
+        rng.reseed([60388u32]);
+
+        let mut v = CowVec::new();
+        for i in range(0, len) {
+            v.push(i);
+        }
+        let mut expected: Vec<uint> = range(0, len).collect();
+
+        for _ in range(0, 100u) {
+            let a = rng.gen_range(0, len + 1);
+            let b = if rng.gen_range(0u, 2) == 0 {
+                rng.gen_range(0, min(len - a, 100) + 1)
+            } else {
+                rng.gen_range(0, len - a + 1)
+            };
+
+            let (left, rest) = v.split_at(a);
+            let (piece, right) = rest.split_at(b);
+            for part in [&left, &rest, &piece, &right].iter() {
+                part.check();
+            }
+            v = left.concat(&right).concat(&piece);
+
+            let mut moved = Vec::with_capacity(len);
+            moved.push_all(expected.as_slice().slice(0, a));
+            moved.push_all(expected.as_slice().slice(a + b, len));
+            moved.push_all(expected.as_slice().slice(a, a + b));
+            expected = moved;
+            check(&v, &expected);
+        }
+    }
+
+    #[test]
+    fn vec_10() { vec_n(10) }
+    #[test]
+    fn vec_1_000() { vec_n(1_000) }
+    #[test]
+    fn vec_100_000() { vec_n(100_000) }
+
+    #[test]
+    fn split_concat_10() { split_concat_n(10) }
+    #[test]
+    fn split_concat_1_000() { split_concat_n(1_000) }
+    #[test]
+    fn split_concat_100_000() { split_concat_n(100_000) }
+}
+
+mod pqueue {
+    use std::iter::range_step;
+    use cow::pqueue::CowPriorityQueue;
+
+    fn priority(i: uint) -> uint
+    {
+        (i * 7919) % 100
+    }
+
+    // every third entry is moved to the front after the pushes
+    fn changed(i: uint) -> uint
+    {
+        if i % 3 == 0 { 0 } else { priority(i) }
+    }
+
+    fn pqueue_n(len: uint)
+    {
+        let mut queue = CowPriorityQueue::new();
+        let mut handles = Vec::new();
+        for i in range(0, len) {
+            handles.push(queue.push(priority(i), i));
+        }
+        assert!(queue.len() == len);
+
+        for i in range_step(0, len, 3) {
+            assert!(queue.change_priority(handles.get(i), 0));
+            assert!(*queue.priority(handles.get(i)).unwrap() == 0);
+        }
+        let snapshot = queue.clone();
+
+        // equal priorities come out in the order they were pushed
+        let mut expected: Vec<(uint, uint)> = range(0, len)
+            .map(|i| (changed(i), i)).collect();
+        expected.sort();
+        for &(p, i) in expected.iter() {
+            assert!(queue.peek_min() == Some((&p, &i)));
+            assert!(queue.pop_min() == Some((p, i)));
+        }
+        assert!(queue.pop_min().is_none());
+        assert!(!queue.change_priority(handles.get(0), 1));
+
+        // from the other end the largest priority comes first
+        let mut queue = snapshot.clone();
+        let mut last = None;
+        while queue.len() != 0 {
+            let (&p, _) = queue.peek_max().unwrap();
+            let (q, _) = queue.pop_max().unwrap();
+            assert!(p == q);
+            match last {
+                Some(l) => assert!(l >= p),
+                None => ()
+            }
+            last = Some(p);
+        }
+
+        let mut queue = snapshot.clone();
+        for i in range(0, len) {
+            if i % 2 == 1 {
+                assert!(queue.remove(handles.get(i)) == Some((changed(i), i)));
+                assert!(queue.remove(handles.get(i)).is_none());
+            }
+        }
+        assert!(queue.len() == len - len / 2);
+        assert!(snapshot.len() == len);
+    }
+
+    #[test]
+    fn pqueue_10() { pqueue_n(10) }
+    #[test]
+    fn pqueue_1_000() { pqueue_n(1_000) }
+    #[test]
+    fn pqueue_100_000() { pqueue_n(100_000) }
+}
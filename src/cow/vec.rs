@@ -0,0 +1,609 @@
+use sync::Arc;
+
+use std::mem;
+use std::default::Default;
+
+// A vector stored as a B-tree without keys. Internal nodes count the
+// entries below them like BTreeMap does, so an index is found by
+// walking down the counts. Nodes are shared between clones and copied
+// on write, so clone is O(1) and every change copies O(log n) nodes.
+//
+// The nodes are not the ones of btree.rs. Those hold fixed arrays of
+// keys with the values and are searched by key, here there are no keys
+// and split_at and concat move whole runs of children from one node to
+// another, which growable nodes make simple. Without keys a leaf also
+// holds twice as many entries.
+
+static VEC_LEAF_SIZE: uint = 64;
+static VEC_INTERNAL_SIZE: uint = 32;
+
+struct NodeInternal<V> {
+    total_len: uint,
+    children: Vec<Node<V>>
+}
+
+enum Node<V> {
+    Leaf(Arc<Vec<V>>),
+    Internal(Arc<NodeInternal<V>>)
+}
+
+pub struct CowVec<V> {
+    root: Node<V>
+}
+
+// moves the entries from at onwards into a new vec
+fn split_off<T>(v: &mut Vec<T>, at: uint) -> Vec<T>
+{
+    let mut tail = Vec::with_capacity(v.len() - at);
+    while v.len() > at {
+        tail.push(v.pop().unwrap());
+    }
+    tail.as_mut_slice().reverse();
+    tail
+}
+
+fn empty<V: Clone+Send+Share>() -> Node<V>
+{
+    Leaf(Arc::new(Vec::new()))
+}
+
+impl<V: Clone+Send+Share> Clone for Node<V>
+{
+    fn clone(&self) -> Node<V>
+    {
+        match *self {
+            Leaf(ref leaf) => Leaf(leaf.clone()),
+            Internal(ref node) => Internal(node.clone())
+        }
+    }
+}
+
+impl<V: Clone+Send+Share> Clone for NodeInternal<V>
+{
+    fn clone(&self) -> NodeInternal<V>
+    {
+        NodeInternal {
+            total_len: self.total_len,
+            children: self.children.clone()
+        }
+    }
+}
+
+impl<V: Clone+Send+Share> NodeInternal<V>
+{
+    fn new(children: Vec<Node<V>>) -> NodeInternal<V>
+    {
+        let total_len = children.iter().fold(0, |len, child| len + child.len());
+        NodeInternal {
+            total_len: total_len,
+            children: children
+        }
+    }
+
+    // the child holding idx and the index within it, an idx one past
+    // the end is placed at the end of the last child
+    fn locate(&self, idx: uint) -> (uint, uint)
+    {
+        let mut idx = idx;
+        let mut i = 0;
+        while i < self.children.len()-1 && idx >= self.children.get(i).len() {
+            idx -= self.children.get(i).len();
+            i += 1;
+        }
+        (i, idx)
+    }
+
+    fn split(&mut self) -> NodeInternal<V>
+    {
+        let half = self.children.len() / 2;
+        let right = NodeInternal::new(split_off(&mut self.children, half));
+        self.total_len -= right.total_len;
+        right
+    }
+
+    // refill the child at idx from one of its neighbours
+    fn rebalance(&mut self, idx: uint)
+    {
+        if self.children.len() < 2 {
+            return;
+        }
+        let left = if idx + 1 < self.children.len() { idx } else { idx - 1 };
+        let b = self.children.remove(left + 1).unwrap();
+        let a = self.children.remove(left).unwrap();
+        for (i, node) in join_siblings(a, b).move_iter().enumerate() {
+            self.children.insert(left + i, node);
+        }
+    }
+}
+
+impl<V: Clone+Send+Share> Node<V>
+{
+    fn len(&self) -> uint
+    {
+        match *self {
+            Leaf(ref leaf) => leaf.deref().len(),
+            Internal(ref node) => node.deref().total_len
+        }
+    }
+
+    // only the root may hold fewer entries or children than half
+    fn underfull(&self) -> bool
+    {
+        match *self {
+            Leaf(ref leaf) => leaf.deref().len() < VEC_LEAF_SIZE / 2,
+            Internal(ref node) => node.deref().children.len() < VEC_INTERNAL_SIZE / 2
+        }
+    }
+
+    fn height(&self) -> uint
+    {
+        let mut height = 0;
+        let mut node = self;
+        loop {
+            match *node {
+                Leaf(_) => return height,
+                Internal(ref n) => {
+                    height += 1;
+                    node = n.deref().children.get(0);
+                }
+            }
+        }
+    }
+
+    // fails unless the counts add up, every leaf is at the same depth
+    // and no node below the root is underfull or overfull, returns the
+    // height
+    fn check(&self, root: bool) -> uint
+    {
+        if !root && self.underfull() {
+            fail!("underfull node");
+        }
+        match *self {
+            Leaf(ref leaf) => {
+                if leaf.deref().len() > VEC_LEAF_SIZE {
+                    fail!("overfull leaf");
+                }
+                0
+            },
+            Internal(ref node) => {
+                let node = node.deref();
+                if node.children.len() < 2 || node.children.len() > VEC_INTERNAL_SIZE {
+                    fail!("bad number of children: {}", node.children.len());
+                }
+                let mut height = None;
+                let mut len = 0;
+                for child in node.children.iter() {
+                    let h = child.check(false);
+                    if height.is_some() && height != Some(h) {
+                        fail!("leaves at different depths");
+                    }
+                    height = Some(h);
+                    len += child.len();
+                }
+                if len != node.total_len {
+                    fail!("total_len {} but {} entries", node.total_len, len);
+                }
+                height.unwrap() + 1
+            }
+        }
+    }
+
+    fn get<'a>(&'a self, idx: uint) -> &'a V
+    {
+        let mut idx = idx;
+        let mut node = self;
+        loop {
+            match *node {
+                Leaf(ref leaf) => return leaf.deref().get(idx),
+                Internal(ref n) => {
+                    let n = n.deref();
+                    let (i, rest) = n.locate(idx);
+                    idx = rest;
+                    node = n.children.get(i);
+                }
+            }
+        }
+    }
+
+    fn get_mut<'a>(&'a mut self, idx: uint) -> &'a mut V
+    {
+        match *self {
+            Leaf(ref mut leaf) => leaf.make_unique().get_mut(idx),
+            Internal(ref mut node) => {
+                let node = node.make_unique();
+                let (i, rest) = node.locate(idx);
+                node.children.get_mut(i).get_mut(rest)
+            }
+        }
+    }
+
+    // returns the new right sibling when self had to be split
+    fn insert(&mut self, idx: uint, value: V) -> Option<Node<V>>
+    {
+        match *self {
+            Leaf(ref mut leaf) => {
+                let leaf = leaf.make_unique();
+                leaf.insert(idx, value);
+                if leaf.len() > VEC_LEAF_SIZE {
+                    let half = leaf.len() / 2;
+                    Some(Leaf(Arc::new(split_off(leaf, half))))
+                } else {
+                    None
+                }
+            },
+            Internal(ref mut node) => {
+                let node = node.make_unique();
+                let (i, rest) = node.locate(idx);
+                node.total_len += 1;
+                match node.children.get_mut(i).insert(rest, value) {
+                    Some(right) => node.children.insert(i + 1, right),
+                    None => ()
+                }
+                if node.children.len() > VEC_INTERNAL_SIZE {
+                    Some(Internal(Arc::new(node.split())))
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    // the parent refills self if it is left underfull
+    fn remove(&mut self, idx: uint) -> V
+    {
+        match *self {
+            Leaf(ref mut leaf) => leaf.make_unique().remove(idx).unwrap(),
+            Internal(ref mut node) => {
+                let node = node.make_unique();
+                let (i, rest) = node.locate(idx);
+                let value = node.children.get_mut(i).remove(rest);
+                node.total_len -= 1;
+                if node.children.get(i).underfull() {
+                    node.rebalance(i);
+                }
+                value
+            }
+        }
+    }
+}
+
+// two neighbours of the same height as one or two nodes, neither of them
+// underfull unless everything fits in one
+fn join_siblings<V: Clone+Send+Share>(a: Node<V>, b: Node<V>) -> Vec<Node<V>>
+{
+    if !a.underfull() && !b.underfull() {
+        return vec!(a, b);
+    }
+
+    match (a, b) {
+        (Leaf(a), Leaf(b)) => {
+            let mut a = a;
+            let mut b = b;
+            let right = {
+                let entries = a.make_unique();
+                entries.push_all_move(mem::replace(b.make_unique(), Vec::new()));
+                if entries.len() > VEC_LEAF_SIZE {
+                    let half = entries.len() / 2;
+                    Some(split_off(entries, half))
+                } else {
+                    None
+                }
+            };
+            match right {
+                Some(right) => vec!(Leaf(a), Leaf(Arc::new(right))),
+                None => vec!(Leaf(a))
+            }
+        },
+        (Internal(a), Internal(b)) => {
+            let mut a = a;
+            let mut b = b;
+            let right = {
+                let node = a.make_unique();
+                let other = b.make_unique();
+                node.total_len += other.total_len;
+                node.children.push_all_move(mem::replace(&mut other.children, Vec::new()));
+                if node.children.len() > VEC_INTERNAL_SIZE {
+                    Some(node.split())
+                } else {
+                    None
+                }
+            };
+            match right {
+                Some(right) => vec!(Internal(a), Internal(Arc::new(right))),
+                None => vec!(Internal(a))
+            }
+        },
+        (_, _) => fail!("siblings should be of the same height")
+    }
+}
+
+// b appended below the right edge of the higher tree a, as one or two
+// nodes as high as a
+fn join_right<V: Clone+Send+Share>(a: Node<V>, ha: uint, b: Node<V>, hb: uint) -> Vec<Node<V>>
+{
+    let mut a = a;
+    let right = match a {
+        Internal(ref mut node) => {
+            let node = node.make_unique();
+            node.total_len += b.len();
+            let last = node.children.pop().unwrap();
+            let joined = if ha == hb + 1 {
+                join_siblings(last, b)
+            } else {
+                join_right(last, ha - 1, b, hb)
+            };
+            node.children.push_all_move(joined);
+            if node.children.len() > VEC_INTERNAL_SIZE {
+                Some(node.split())
+            } else {
+                None
+            }
+        },
+        Leaf(_) => fail!("a leaf is never the higher tree")
+    };
+    match right {
+        Some(right) => vec!(a, Internal(Arc::new(right))),
+        None => vec!(a)
+    }
+}
+
+// a prepended below the left edge of the higher tree b
+fn join_left<V: Clone+Send+Share>(a: Node<V>, ha: uint, b: Node<V>, hb: uint) -> Vec<Node<V>>
+{
+    let mut b = b;
+    let right = match b {
+        Internal(ref mut node) => {
+            let node = node.make_unique();
+            node.total_len += a.len();
+            let first = node.children.remove(0).unwrap();
+            let joined = if hb == ha + 1 {
+                join_siblings(a, first)
+            } else {
+                join_left(a, ha, first, hb - 1)
+            };
+            for (i, child) in joined.move_iter().enumerate() {
+                node.children.insert(i, child);
+            }
+            if node.children.len() > VEC_INTERNAL_SIZE {
+                Some(node.split())
+            } else {
+                None
+            }
+        },
+        Leaf(_) => fail!("a leaf is never the higher tree")
+    };
+    match right {
+        Some(right) => vec!(b, Internal(Arc::new(right))),
+        None => vec!(b)
+    }
+}
+
+// a tree holding the nodes, which are all of the same height
+fn tree_of<V: Clone+Send+Share>(nodes: Vec<Node<V>>) -> Node<V>
+{
+    let mut nodes = nodes;
+    match nodes.len() {
+        0 => empty(),
+        1 => nodes.pop().unwrap(),
+        _ => Internal(Arc::new(NodeInternal::new(nodes)))
+    }
+}
+
+// the entries of a followed by the entries of b, only the nodes along
+// the edge where they meet are copied
+fn concat<V: Clone+Send+Share>(a: Node<V>, b: Node<V>) -> Node<V>
+{
+    if a.len() == 0 {
+        return b;
+    }
+    if b.len() == 0 {
+        return a;
+    }
+
+    let (ha, hb) = (a.height(), b.height());
+    tree_of(if ha == hb {
+        join_siblings(a, b)
+    } else if ha > hb {
+        join_right(a, ha, b, hb)
+    } else {
+        join_left(a, ha, b, hb)
+    })
+}
+
+// the entries before idx and the rest as two trees, the subtrees on
+// either side of the path to idx are reused as they are
+fn split<V: Clone+Send+Share>(node: Node<V>, idx: uint) -> (Node<V>, Node<V>)
+{
+    match node {
+        Leaf(leaf) => {
+            let mut leaf = leaf;
+            let right = split_off(leaf.make_unique(), idx);
+            (Leaf(leaf), Leaf(Arc::new(right)))
+        },
+        Internal(node) => {
+            let mut node = node;
+            let (i, rest) = node.deref().locate(idx);
+            let mut before = mem::replace(&mut node.make_unique().children, Vec::new());
+            let after = split_off(&mut before, i + 1);
+            let (left, right) = split(before.pop().unwrap(), rest);
+            (concat(tree_of(before), left), concat(right, tree_of(after)))
+        }
+    }
+}
+
+impl<V: Clone+Send+Share> CowVec<V>
+{
+    pub fn new() -> CowVec<V>
+    {
+        CowVec {
+            root: empty()
+        }
+    }
+
+    pub fn get<'a>(&'a self, idx: uint) -> &'a V
+    {
+        if idx >= self.len() {
+            fail!("index out of bounds");
+        }
+        self.root.get(idx)
+    }
+
+    pub fn set(&mut self, idx: uint, value: V)
+    {
+        if idx >= self.len() {
+            fail!("index out of bounds");
+        }
+        *self.root.get_mut(idx) = value;
+    }
+
+    pub fn push(&mut self, value: V)
+    {
+        let len = self.len();
+        self.insert(len, value)
+    }
+
+    pub fn pop(&mut self) -> Option<V>
+    {
+        match self.len() {
+            0 => None,
+            len => Some(self.remove(len - 1))
+        }
+    }
+
+    // shifts the entries from idx on to the right
+    pub fn insert(&mut self, idx: uint, value: V)
+    {
+        if idx > self.len() {
+            fail!("index out of bounds");
+        }
+        match self.root.insert(idx, value) {
+            Some(right) => {
+                let left = mem::replace(&mut self.root, empty());
+                self.root = Internal(Arc::new(NodeInternal::new(vec!(left, right))));
+            },
+            None => ()
+        }
+    }
+
+    // shifts the entries after idx to the left
+    pub fn remove(&mut self, idx: uint) -> V
+    {
+        if idx >= self.len() {
+            fail!("index out of bounds");
+        }
+        let value = self.root.remove(idx);
+
+        // a root with a single child is replaced by it
+        loop {
+            let child = match self.root {
+                Internal(ref node) if node.deref().children.len() == 1 => node.deref().children.get(0).clone(),
+                _ => break
+            };
+            self.root = child;
+        }
+        value
+    }
+
+    // the entries before idx and the rest, both share their nodes with self
+    pub fn split_at(&self, idx: uint) -> (CowVec<V>, CowVec<V>)
+    {
+        if idx > self.len() {
+            fail!("index out of bounds");
+        }
+        let (left, right) = split(self.root.clone(), idx);
+        (CowVec {root: left}, CowVec {root: right})
+    }
+
+    // the entries of self followed by those of other
+    pub fn concat(&self, other: &CowVec<V>) -> CowVec<V>
+    {
+        CowVec {
+            root: concat(self.root.clone(), other.root.clone())
+        }
+    }
+
+    // used by the tests to check the shape of the tree
+    #[doc(hidden)]
+    pub fn check(&self)
+    {
+        self.root.check(true);
+    }
+
+    pub fn iter<'a>(&'a self) -> CowVecIterator<'a, V>
+    {
+        let mut iter = CowVecIterator {
+            stack: Vec::new(),
+            leaf: None
+        };
+        iter.descend(&self.root);
+        iter
+    }
+}
+
+impl<V: Clone+Send+Share> Container for CowVec<V> {
+    fn len(&self) -> uint
+    {
+        self.root.len()
+    }
+}
+
+impl<V: Clone+Send+Share> Clone for CowVec<V>
+{
+    fn clone(&self) -> CowVec<V>
+    {
+        CowVec {
+            root: self.root.clone()
+        }
+    }
+}
+
+impl<V: Clone+Send+Share> Default for CowVec<V>
+{
+    fn default() -> CowVec<V>
+    {
+        CowVec::new()
+    }
+}
+
+pub struct CowVecIterator<'a, V> {
+    stack: Vec<(&'a NodeInternal<V>, uint)>,
+    leaf: Option<(&'a Vec<V>, uint)>
+}
+
+impl<'a, V: Clone+Send+Share> CowVecIterator<'a, V>
+{
+    fn descend(&mut self, node: &'a Node<V>)
+    {
+        match *node {
+            Leaf(ref leaf) => self.leaf = Some((leaf.deref(), 0)),
+            Internal(ref n) => self.stack.push((n.deref(), 0))
+        }
+    }
+}
+
+impl<'a, V: Clone+Send+Share> Iterator<&'a V> for CowVecIterator<'a, V>
+{
+    fn next(&mut self) -> Option<&'a V>
+    {
+        loop {
+            let leaf = self.leaf;
+            match leaf {
+                Some((entries, idx)) if idx < entries.len() => {
+                    self.leaf = Some((entries, idx + 1));
+                    return Some(entries.get(idx));
+                },
+                _ => self.leaf = None
+            }
+
+            let (node, idx) = match self.stack.pop() {
+                Some(top) => top,
+                None => return None
+            };
+            if idx == node.children.len() {
+                continue;
+            }
+            self.stack.push((node, idx + 1));
+            self.descend(node.children.get(idx));
+        }
+    }
+}
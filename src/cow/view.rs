@@ -0,0 +1,181 @@
+use std::default::Default;
+
+use btree::{BTreeMap, BTreeSet, Change, Added, Removed, Changed};
+
+// Derived data kept up to date from the changes to a source map.
+pub trait View<K, V> {
+    fn apply(&mut self, change: &Change<K, V>);
+}
+
+// Remembers the version of the source its views were last brought up to
+// date with. Since the source is copy on write, comparing that version
+// with the current one only visits the nodes modified in between.
+pub struct ViewDriver<K, V> {
+    last: BTreeMap<K, V>
+}
+
+impl<K: Default+Clone+TotalOrd+Send+Share, V: Eq+Default+Clone+Send+Share> ViewDriver<K, V>
+{
+    // the first refresh feeds every entry of the source as Added
+    pub fn new() -> ViewDriver<K, V>
+    {
+        ViewDriver {
+            last: BTreeMap::new()
+        }
+    }
+
+    // apply the changes since the last refresh to every view, returns the
+    // number of changes
+    pub fn refresh(&mut self, source: &BTreeMap<K, V>, views: &mut [&mut View<K, V>]) -> uint
+    {
+        let changes = self.last.diff(source);
+        for change in changes.iter() {
+            for view in views.mut_iter() {
+                view.apply(change);
+            }
+        }
+        self.last = source.clone();
+        changes.len()
+    }
+}
+
+// number of entries in each category
+pub struct CountView<K, V, C> {
+    category: fn(&K, &V) -> C,
+    counts: BTreeMap<C, uint>
+}
+
+impl<K, V, C: Default+Clone+TotalOrd+Send+Share> CountView<K, V, C>
+{
+    pub fn new(category: fn(&K, &V) -> C) -> CountView<K, V, C>
+    {
+        CountView {
+            category: category,
+            counts: BTreeMap::new()
+        }
+    }
+
+    pub fn counts<'a>(&'a self) -> &'a BTreeMap<C, uint>
+    {
+        &self.counts
+    }
+
+    fn add(&mut self, c: C)
+    {
+        match self.counts.find_mut(&c) {
+            Some(count) => {
+                *count += 1;
+                return;
+            },
+            None => ()
+        }
+        self.counts.insert(c, 1);
+    }
+
+    fn remove(&mut self, c: C)
+    {
+        let empty = match self.counts.find_mut(&c) {
+            Some(count) => {
+                *count -= 1;
+                *count == 0
+            },
+            None => fail!("removing from an empty category")
+        };
+        if empty {
+            self.counts.remove(&c);
+        }
+    }
+}
+
+impl<K, V, C: Default+Clone+TotalOrd+Send+Share> View<K, V> for CountView<K, V, C>
+{
+    fn apply(&mut self, change: &Change<K, V>)
+    {
+        match *change {
+            Added(ref k, ref v) => {
+                let c = (self.category)(k, v);
+                self.add(c);
+            },
+            Removed(ref k, ref v) => {
+                let c = (self.category)(k, v);
+                self.remove(c);
+            },
+            Changed(ref k, ref old, ref new) => {
+                let (a, b) = ((self.category)(k, old), (self.category)(k, new));
+                if a.cmp(&b) != Equal {
+                    self.remove(a);
+                    self.add(b);
+                }
+            }
+        }
+    }
+}
+
+// the keys of every entry whose value contains a term
+pub struct InvertedIndex<K, V, T> {
+    terms: fn(&V) -> Vec<T>,
+    index: BTreeMap<T, BTreeSet<K>>
+}
+
+impl<K: Default+Clone+TotalOrd+Send+Share, V, T: Default+Clone+TotalOrd+Send+Share> InvertedIndex<K, V, T>
+{
+    pub fn new(terms: fn(&V) -> Vec<T>) -> InvertedIndex<K, V, T>
+    {
+        InvertedIndex {
+            terms: terms,
+            index: BTreeMap::new()
+        }
+    }
+
+    pub fn index<'a>(&'a self) -> &'a BTreeMap<T, BTreeSet<K>>
+    {
+        &self.index
+    }
+
+    fn add(&mut self, k: &K, v: &V)
+    {
+        for term in (self.terms)(v).move_iter() {
+            match self.index.find_mut(&term) {
+                Some(keys) => {
+                    keys.insert(k.clone());
+                    continue;
+                },
+                None => ()
+            }
+            let mut keys = BTreeSet::new();
+            keys.insert(k.clone());
+            self.index.insert(term, keys);
+        }
+    }
+
+    fn remove(&mut self, k: &K, v: &V)
+    {
+        for term in (self.terms)(v).move_iter() {
+            let empty = match self.index.find_mut(&term) {
+                Some(keys) => {
+                    keys.remove(k);
+                    keys.is_empty()
+                },
+                None => false
+            };
+            if empty {
+                self.index.remove(&term);
+            }
+        }
+    }
+}
+
+impl<K: Default+Clone+TotalOrd+Send+Share, V, T: Default+Clone+TotalOrd+Send+Share> View<K, V> for InvertedIndex<K, V, T>
+{
+    fn apply(&mut self, change: &Change<K, V>)
+    {
+        match *change {
+            Added(ref k, ref v) => self.add(k, v),
+            Removed(ref k, ref v) => self.remove(k, v),
+            Changed(ref k, ref old, ref new) => {
+                self.remove(k, old);
+                self.add(k, new);
+            }
+        }
+    }
+}
@@ -0,0 +1,422 @@
+use std::io;
+use std::io::{IoResult, File, MemWriter, MemReader, BufferedReader, BufferedWriter};
+use std::io::fs;
+
+use btree::BTreeMap;
+use crc::crc32;
+use snapshot::{SnapshotItem, write_snapshot, read_snapshot, invalid};
+
+// On disk a log is a directory holding
+//
+//   wal-<seq>.log       segments, named by the first sequence number
+//                       they may contain
+//   snapshot-<seq>.snap the map with every record up to and
+//                       including <seq> applied
+//
+// each record in a segment is
+//
+//   len u32, crc32 u32 of the payload, payload[len]
+//
+// where the payload is the sequence number (u64), a tag byte and the
+// key and value of the operation. A record that is cut short or fails
+// its crc at the end of the last segment is what a crash mid write looks
+// like, recovery cuts the log off in front of it so later appends follow
+// the last good record. Anywhere else it fails the recovery.
+
+static DEFAULT_SEGMENT_SIZE: u64 = 16 * 1024 * 1024;
+
+pub enum Record<K, V> {
+    LogInsert(K, V),
+    LogPop(K),
+    LogSwap(K, V)
+}
+
+impl<K: SnapshotItem, V: SnapshotItem> Record<K, V>
+{
+    fn write<W: Writer>(&self, seq: u64, w: &mut W) -> IoResult<()>
+    {
+        try!(w.write_le_u64(seq));
+        match *self {
+            LogInsert(ref k, ref v) => {
+                try!(w.write_u8(0));
+                try!(k.write_item(w));
+                v.write_item(w)
+            },
+            LogPop(ref k) => {
+                try!(w.write_u8(1));
+                k.write_item(w)
+            },
+            LogSwap(ref k, ref v) => {
+                try!(w.write_u8(2));
+                try!(k.write_item(w));
+                v.write_item(w)
+            }
+        }
+    }
+
+    fn read<R: Reader>(r: &mut R) -> IoResult<(u64, Record<K, V>)>
+    {
+        let seq = try!(r.read_le_u64());
+        let record = match try!(r.read_u8()) {
+            0 => LogInsert(try!(SnapshotItem::read_item(r)), try!(SnapshotItem::read_item(r))),
+            1 => LogPop(try!(SnapshotItem::read_item(r))),
+            2 => LogSwap(try!(SnapshotItem::read_item(r)), try!(SnapshotItem::read_item(r))),
+            _ => return Err(invalid("bad record tag"))
+        };
+        Ok((seq, record))
+    }
+}
+
+pub struct WriteAheadLog {
+    dir: Path,
+    segment_size: u64,
+    file: File,
+    written: u64,
+    next_seq: u64
+}
+
+impl WriteAheadLog
+{
+    // the next record written will be numbered next_seq
+    fn create(dir: Path, segment_size: u64, next_seq: u64) -> IoResult<WriteAheadLog>
+    {
+        let file = try!(File::open_mode(&segment_path(&dir, next_seq), io::Truncate, io::Write));
+        Ok(WriteAheadLog {
+            dir: dir,
+            segment_size: segment_size,
+            file: file,
+            written: 0,
+            next_seq: next_seq
+        })
+    }
+
+    // write a group of records with a single sync, returns the
+    // sequence number of the last record
+    pub fn append<K: SnapshotItem, V: SnapshotItem>(&mut self, records: &[Record<K, V>]) -> IoResult<u64>
+    {
+        if self.written >= self.segment_size {
+            try!(self.rotate());
+        }
+
+        let mut seq = self.next_seq;
+        let mut buf = MemWriter::new();
+        for record in records.iter() {
+            let mut payload = MemWriter::new();
+            try!(record.write(seq, &mut payload));
+            let payload = payload.unwrap();
+
+            try!(buf.write_le_u32(payload.len() as u32));
+            try!(buf.write_le_u32(crc32(payload.as_slice())));
+            try!(buf.write(payload.as_slice()));
+            seq += 1;
+        }
+
+        let buf = buf.unwrap();
+        let res = match self.file.write(buf.as_slice()) {
+            Ok(()) => self.file.fsync(),
+            Err(e) => Err(e)
+        };
+        match res {
+            Ok(()) => (),
+            Err(e) => {
+                // drop whatever part of the group reached the file, the
+                // next append starts again from the last good record
+                let _ = self.file.truncate(self.written as i64);
+                let _ = self.file.seek(self.written as i64, io::SeekSet);
+                return Err(e);
+            }
+        }
+
+        self.written += buf.len() as u64;
+        self.next_seq = seq;
+        Ok(seq - 1)
+    }
+
+    // start a new segment, records written so far are left in the
+    // previous ones
+    pub fn rotate(&mut self) -> IoResult<()>
+    {
+        self.file = try!(File::open_mode(&segment_path(&self.dir, self.next_seq), io::Truncate, io::Write));
+        self.written = 0;
+        Ok(())
+    }
+
+    // remove every segment holding nothing newer than seq
+    pub fn truncate(&mut self, seq: u64) -> IoResult<()>
+    {
+        let segments = try!(list(&self.dir, "wal-", ".log"));
+        for (i, &(start, ref path)) in segments.iter().enumerate() {
+            let covered = match segments.get(i+1) {
+                Some(&(next, _)) => next <= seq + 1,
+                None => false
+            };
+            if covered && start < self.next_seq {
+                try!(fs::unlink(path));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn next_seq(&self) -> u64
+    {
+        self.next_seq
+    }
+}
+
+pub struct DurableBTreeMap<K, V> {
+    map: BTreeMap<K, V>,
+    log: Option<WriteAheadLog>,
+    batch: Option<Vec<Record<K, V>>>
+}
+
+impl<K: SnapshotItem+Default+Clone+TotalOrd+Send+Share, V: SnapshotItem+Default+Clone+Send+Share> DurableBTreeMap<K, V>
+{
+    // a map that does not log anything
+    pub fn new(map: BTreeMap<K, V>) -> DurableBTreeMap<K, V>
+    {
+        DurableBTreeMap {
+            map: map,
+            log: None,
+            batch: None
+        }
+    }
+
+    pub fn open(dir: &Path) -> IoResult<DurableBTreeMap<K, V>>
+    {
+        DurableBTreeMap::open_with_segment_size(dir, DEFAULT_SEGMENT_SIZE)
+    }
+
+    // load the newest snapshot and replay the log on top of it
+    pub fn open_with_segment_size(dir: &Path, segment_size: u64) -> IoResult<DurableBTreeMap<K, V>>
+    {
+        try!(fs::mkdir_recursive(dir, io::UserRWX));
+
+        let (mut map, mut last) = match try!(list(dir, "snapshot-", ".snap")).pop() {
+            Some((seq, path)) => {
+                let mut r = BufferedReader::new(try!(File::open(&path)));
+                (try!(read_snapshot(&mut r)), Some(seq))
+            },
+            None => (BTreeMap::new(), None)
+        };
+
+        // a record cut short or failing its crc can only be the last one
+        // written before a crash, anywhere else the log is corrupt
+        let segments = try!(list(dir, "wal-", ".log"));
+        let mut torn = None;
+        'segments: for (i, &(_, ref path)) in segments.iter().enumerate() {
+            let data = try!(try!(File::open(path)).read_to_end());
+            let size = data.len();
+            let mut r = MemReader::new(data);
+            let mut good = 0;
+            loop {
+                if r.eof() {
+                    break;
+                }
+                let payload = match read_record(&mut r, size - good) {
+                    Some(payload) => payload,
+                    None if i + 1 == segments.len() => {
+                        torn = Some(good);
+                        break 'segments;
+                    },
+                    None => return Err(invalid("corrupt record before the end of the log"))
+                };
+                good += 8 + payload.len();
+
+                let (seq, record) = try!(Record::read(&mut MemReader::new(payload)));
+                match last {
+                    Some(l) if seq <= l => continue,
+                    _ => ()
+                }
+                match record {
+                    LogInsert(k, v) | LogSwap(k, v) => { map.insert(k, v); },
+                    LogPop(k) => { map.pop(&k); }
+                }
+                last = Some(seq);
+            }
+        }
+
+        // cut the torn record off, otherwise the records appended from
+        // here on would sit behind it and be lost on the next replay
+        match torn {
+            Some(good) => {
+                let &(_, ref path) = segments.last().unwrap();
+                let mut file = try!(File::open_mode(path, io::Open, io::Write));
+                try!(file.truncate(good as i64));
+                try!(file.fsync());
+            },
+            None => ()
+        }
+
+        let next = match last {
+            Some(seq) => seq + 1,
+            None => 0
+        };
+
+        Ok(DurableBTreeMap {
+            map: map,
+            log: Some(try!(WriteAheadLog::create(dir.clone(), segment_size, next))),
+            batch: None
+        })
+    }
+
+    pub fn map<'a>(&'a self) -> &'a BTreeMap<K, V>
+    {
+        &self.map
+    }
+
+    // operations until commit_batch are applied to the map right away
+    // but only become durable together once the batch is committed
+    pub fn begin_batch(&mut self)
+    {
+        if self.batch.is_none() {
+            self.batch = Some(Vec::new());
+        }
+    }
+
+    // the map already holds the batch, so if it cannot be written the
+    // batch stays open and the commit can be retried
+    pub fn commit_batch(&mut self) -> IoResult<()>
+    {
+        let records = match self.batch.take() {
+            Some(records) => records,
+            None => return Ok(())
+        };
+        match self.log {
+            Some(ref mut log) if records.len() != 0 => {
+                match log.append(records.as_slice()) {
+                    Ok(_) => Ok(()),
+                    Err(e) => {
+                        self.batch = Some(records);
+                        Err(e)
+                    }
+                }
+            },
+            _ => Ok(())
+        }
+    }
+
+    fn record(&mut self, record: Record<K, V>) -> IoResult<()>
+    {
+        match self.batch {
+            Some(ref mut batch) => {
+                batch.push(record);
+                return Ok(());
+            },
+            None => ()
+        }
+
+        match self.log {
+            Some(ref mut log) => {
+                try!(log.append(&[record]));
+                Ok(())
+            },
+            None => Ok(())
+        }
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> IoResult<bool>
+    {
+        if self.log.is_some() {
+            try!(self.record(LogInsert(key.clone(), value.clone())));
+        }
+        Ok(self.map.insert(key, value))
+    }
+
+    pub fn pop(&mut self, key: &K) -> IoResult<Option<V>>
+    {
+        if self.log.is_some() && self.map.contains_key(key) {
+            try!(self.record(LogPop(key.clone())));
+        }
+        Ok(self.map.pop(key))
+    }
+
+    pub fn swap(&mut self, key: K, value: V) -> IoResult<Option<V>>
+    {
+        if self.log.is_some() {
+            try!(self.record(LogSwap(key.clone(), value.clone())));
+        }
+        Ok(self.map.swap(key, value))
+    }
+
+    // write a snapshot of every committed record and drop the log
+    // segments and older snapshots it covers
+    pub fn checkpoint(&mut self) -> IoResult<()>
+    {
+        try!(self.commit_batch());
+
+        let log = match self.log {
+            Some(ref mut log) => log,
+            None => return Ok(())
+        };
+
+        if log.next_seq == 0 {
+            return Ok(());
+        }
+        let seq = log.next_seq - 1;
+
+        let tmp = log.dir.join(format!("snapshot-{:020u}.tmp", seq));
+        let path = log.dir.join(format!("snapshot-{:020u}.snap", seq));
+        {
+            let mut w = BufferedWriter::new(try!(File::create(&tmp)));
+            try!(write_snapshot(&self.map, &mut w));
+            let mut file = w.unwrap();
+            try!(file.fsync());
+        }
+        try!(fs::rename(&tmp, &path));
+
+        try!(log.rotate());
+        try!(log.truncate(seq));
+        for &(old, ref p) in try!(list(&log.dir, "snapshot-", ".snap")).iter() {
+            if old < seq {
+                try!(fs::unlink(p));
+            }
+        }
+        Ok(())
+    }
+}
+
+// the payload of the next record, None if it is cut short or fails its
+// crc. left is the number of bytes left in the segment, the length read
+// from the file is checked against it before anything is allocated.
+fn read_record(r: &mut MemReader, left: uint) -> Option<~[u8]>
+{
+    if left < 8 {
+        return None;
+    }
+    let (len, crc) = match (r.read_le_u32(), r.read_le_u32()) {
+        (Ok(len), Ok(crc)) => (len as uint, crc),
+        _ => return None
+    };
+    if len > left - 8 {
+        return None;
+    }
+    match r.read_bytes(len) {
+        Ok(payload) if crc32(payload.as_slice()) == crc => Some(payload),
+        _ => None
+    }
+}
+
+fn segment_path(dir: &Path, seq: u64) -> Path
+{
+    dir.join(format!("wal-{:020u}.log", seq))
+}
+
+// files in dir named prefix<seq>suffix, ordered by seq
+fn list(dir: &Path, prefix: &str, suffix: &str) -> IoResult<Vec<(u64, Path)>>
+{
+    let mut out = Vec::new();
+    for path in try!(fs::readdir(dir)).move_iter() {
+        let seq = match path.filename_str() {
+            Some(name) if name.starts_with(prefix) && name.ends_with(suffix) => {
+                from_str::<u64>(name.slice(prefix.len(), name.len() - suffix.len()))
+            },
+            _ => None
+        };
+        match seq {
+            Some(seq) => out.push((seq, path)),
+            None => ()
+        }
+    }
+    out.as_mut_slice().sort_by(|&(a, _), &(b, _)| a.cmp(&b));
+    Ok(out)
+}